@@ -1,89 +1,186 @@
-use enum_map::EnumMap;
-use hashbrown::{hash_map::EntryRef, HashMap};
-use macroquad::texture::{FilterMode, Image, Texture2D};
+use hashbrown::HashMap;
+use macroquad::texture::{Image, Texture2D};
 
 use crate::{
+  atlas::{Atlas, AtlasRegion},
   debug::DebugState,
+  hot_reload::ChangedAsset,
   loading,
+  math::position_hash,
   palette::{colorize_sprite, PaletteName},
-  sprite::{NineSliceDir, SpriteId, SpriteNineGroup, SpriteNinePaletteKey, SpritePaletteKey},
+  registry::init_registry,
+  sprite::{
+    SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNinePaletteKey, SpritePaletteKey,
+  },
   ui::Fonts,
 };
 
 pub struct Sprites {
-  pub empty: Image,
   pub empty_full: Image,
   pub palette: Image,
   pub palettes: HashMap<PaletteName, (Texture2D, Texture2D, Texture2D)>,
-  pub sprites: EnumMap<SpriteId, Vec<Image>>,
-  pub nine_slice_sprites: EnumMap<SpriteNineGroup, HashMap<u8, EnumMap<NineSliceDir, Image>>>,
+  //Indexed by the ids of the sprite registry.
+  pub sprites: Vec<Vec<Image>>,
+  pub group_sprites: Vec<Vec<Image>>,
+  pub nine_slice_sprites: Vec<HashMap<u8, Vec<Image>>>,
 }
 
 pub struct Assets {
   pub fonts: Fonts,
   pub frame_beginning: f64,
   pub sprites: Sprites,
-  texs: HashMap<SpritePaletteKey, Texture2D>,
-  nine_slices: HashMap<SpriteNinePaletteKey, Texture2D>,
-  empty: Texture2D,
+  atlas: Atlas,
+  //Keyed by sprite and whether the background is filled in.
+  texs: HashMap<(SpritePaletteKey, bool), AtlasRegion>,
+  groups: HashMap<(SpriteGroupPaletteKey, bool), AtlasRegion>,
+  nine_slices: HashMap<(SpriteNinePaletteKey, bool), AtlasRegion>,
 }
 
 impl Assets {
-  pub fn get_texture(&mut self, key: &SpritePaletteKey) -> &Texture2D {
-    match self.texs.entry_ref(key) {
-      EntryRef::Vacant(v) => {
-        let t = Texture2D::from_image(&colorize_sprite(
-          &self.sprites.sprites[key.sid][0],
-          &key.foreground,
-          Some(&key.foreground),
-        ));
-        t.set_filter(FilterMode::Nearest);
-        v.insert(t);
+  /** `filled` paints the transparent pixels with the background, see `World::draw`. */
+  pub fn get_texture(&mut self, key: &SpritePaletteKey, filled: bool) -> &AtlasRegion {
+    let sprite = &self.sprites.sprites[key.sid.index()][0];
+    let atlas = &mut self.atlas;
+    self.texs.entry((key.clone(), filled)).or_insert_with(|| {
+      atlas.insert(&colorize_sprite(
+        sprite,
+        &key.foreground,
+        filled.then_some(&key.background),
+      ))
+    })
+  }
+  /** Texture of any sprite key, automatic group variants are picked for the cell at x,y. */
+  pub fn get_key_texture(
+    &mut self,
+    key: &SpriteKey,
+    x: &usize,
+    y: &usize,
+    filled: bool,
+  ) -> &AtlasRegion {
+    match key {
+      SpriteKey::Specific(sprite_palette) => self.get_texture(sprite_palette, filled),
+      SpriteKey::Nine(sprite_nine_palette) => self.get_nine_slice(sprite_nine_palette, filled),
+      SpriteKey::Group(sprite_group_palette) => {
+        let key = self.resolve_group_key(sprite_group_palette, x, y);
+        self.get_group_texture(&key, filled)
       }
-      _ => {}
-    };
-
-    self.texs.get(key).unwrap()
+    }
   }
-  pub fn get_nine_slice(&mut self, key: &SpriteNinePaletteKey) -> &Texture2D {
-    match self.nine_slices.entry_ref(key) {
-      EntryRef::Vacant(v) => {
-        let group = &self.sprites.nine_slice_sprites[key.sg].get(&key.sg_index);
-        let t = if let Some(nine_sprites) = group {
-          Texture2D::from_image(&colorize_sprite(
-            &nine_sprites[key.nine_dir],
-            &key.foreground,
-            Some(&key.background),
-          ))
-        } else {
-          Texture2D::from_image(&colorize_sprite(
-            &self.sprites.sprites[SpriteId::Missing][0],
-            &key.foreground,
-            Some(&key.background),
-          ))
-        };
-
-        t.set_filter(FilterMode::Nearest);
-        v.insert(t);
+  /** Number of variants loaded for a sprite group. */
+  pub fn group_variant_count(&self, sg: &SpriteGroup) -> usize {
+    self.sprites.group_sprites[sg.index()].len()
+  }
+  /** Resolves an automatic group index to a concrete variant for the tile at x,y. */
+  pub fn resolve_group_key(
+    &self,
+    key: &SpriteGroupPaletteKey,
+    x: &usize,
+    y: &usize,
+  ) -> SpriteGroupPaletteKey {
+    let mut resolved = SpriteGroupPaletteKey::from(key);
+    if resolved.sg_index.is_none() {
+      let count = self.group_variant_count(&key.sg).max(1);
+      resolved.sg_index = Some((position_hash(x, y) as usize % count) as u8);
+    }
+    resolved
+  }
+  /** Expects a resolved key, see `resolve_group_key`. */
+  pub fn get_group_texture(&mut self, key: &SpriteGroupPaletteKey, filled: bool) -> &AtlasRegion {
+    let variant = self.sprites.group_sprites[key.sg.index()]
+      .get(key.sg_index.unwrap_or(0) as usize)
+      .unwrap_or(&self.sprites.sprites[SpriteId::MISSING.index()][0]);
+    let atlas = &mut self.atlas;
+    self.groups.entry((key.clone(), filled)).or_insert_with(|| {
+      atlas.insert(&colorize_sprite(
+        variant,
+        &key.foreground,
+        filled.then_some(&key.background),
+      ))
+    })
+  }
+  pub fn get_nine_slice(&mut self, key: &SpriteNinePaletteKey, filled: bool) -> &AtlasRegion {
+    let blob_tile = self.sprites.nine_slice_sprites[key.sg.index()]
+      .get(&key.sg_index)
+      .and_then(|blob_tiles| blob_tiles.get(key.blob.index()))
+      .unwrap_or(&self.sprites.sprites[SpriteId::MISSING.index()][0]);
+    let atlas = &mut self.atlas;
+    self
+      .nine_slices
+      .entry((key.clone(), filled))
+      .or_insert_with(|| {
+        atlas.insert(&colorize_sprite(
+          blob_tile,
+          &key.foreground,
+          filled.then_some(&key.background),
+        ))
+      })
+  }
+  /**
+  Reloads the frames of changed sprites and drops only the cached textures made from them.
+  Returns the names of the reloaded sprites, the world is reloaded by the caller.
+  */
+  pub async fn reload(&mut self, deb: &DebugState, changed: &[ChangedAsset]) -> Vec<String> {
+    let mut reloaded = vec![];
+    for asset in changed {
+      let def = match asset {
+        ChangedAsset::Sprite(sid) => sid.def(),
+        ChangedAsset::Group(sg) => sg.def(),
+        ChangedAsset::Nine(sg) => sg.def(),
+        ChangedAsset::World => continue,
+      };
+      let frames = match loading::load_sprite_frames(deb, def).await {
+        Ok(frames) => frames,
+        Err(e) => {
+          println!("Could not reload '{}': {}", def.name, e);
+          continue;
+        }
+      };
+      match asset {
+        ChangedAsset::Sprite(sid) => {
+          self.sprites.sprites[sid.index()] = frames;
+          self.texs.retain(|(key, _), _| key.sid != *sid);
+          //Groups and nine groups fall back to the missing sprite.
+          if *sid == SpriteId::MISSING {
+            self.groups.clear();
+            self.nine_slices.clear();
+          }
+        }
+        ChangedAsset::Group(sg) => {
+          self.sprites.group_sprites[sg.index()] = frames;
+          self.groups.retain(|(key, _), _| key.sg != *sg);
+        }
+        ChangedAsset::Nine(sg) => {
+          self.sprites.nine_slice_sprites[sg.index()] = loading::slice_nine_sheets(&frames);
+          self.nine_slices.retain(|(key, _), _| key.sg != *sg);
+        }
+        ChangedAsset::World => {}
       }
-      _ => {}
-    };
-
-    self.nine_slices.get(key).unwrap()
+      reloaded.push(def.name.clone());
+    }
+    reloaded
+  }
+  /** Uploads sprites added to the atlas this frame, call before `next_frame`. */
+  pub fn flush_atlas(&mut self) {
+    self.atlas.flush();
   }
-  pub fn get_empty_tex(&self) -> Texture2D {
-    self.empty
+  pub fn atlas_page_count(&self) -> usize {
+    self.atlas.page_count()
   }
   pub async fn new(deb: &DebugState) -> Assets {
     let fonts = loading::load_fonts().await;
-    let sprites = loading::load_sprites(deb).await;
-    let empty = Texture2D::from_image(&sprites.empty);
-    empty.set_filter(FilterMode::Nearest);
+    match loading::load_registry().await {
+      Ok(registry) => init_registry(registry),
+      Err(e) => panic!("{}", e),
+    }
+    let sprites = loading::load_sprites(deb)
+      .await
+      .unwrap_or_else(|e| panic!("{}", e));
     Assets {
-      empty,
+      atlas: Atlas::default(),
       sprites,
       fonts,
       texs: HashMap::new(),
+      groups: HashMap::new(),
       nine_slices: HashMap::new(),
       frame_beginning: 0.0,
     }
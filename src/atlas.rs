@@ -0,0 +1,179 @@
+use macroquad::{
+  math::{vec2, Rect},
+  prelude::{BLANK, WHITE},
+  texture::{draw_texture_ex, DrawTextureParams, FilterMode, Image, Texture2D},
+};
+
+/** Width and height of an atlas page in pixels. Fits 3600 padded 16x16 sprites. */
+pub const ATLAS_PAGE_SIZE: usize = 1024;
+//Empty pixels around every sprite so neighbours never bleed in at fractional zoom.
+const PADDING: usize = 1;
+
+/** Where a sprite ended up in the atlas. */
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasRegion {
+  pub page: usize,
+  pub texture: Texture2D,
+  pub source: Rect,
+}
+
+struct Shelf {
+  top: usize,
+  height: usize,
+  right: usize,
+}
+
+struct AtlasPage {
+  image: Image,
+  texture: Texture2D,
+  shelves: Vec<Shelf>,
+  dirty: bool,
+}
+
+impl AtlasPage {
+  fn new() -> AtlasPage {
+    let image = Image::gen_image_color(ATLAS_PAGE_SIZE as u16, ATLAS_PAGE_SIZE as u16, BLANK);
+    let texture = Texture2D::from_image(&image);
+    texture.set_filter(FilterMode::Nearest);
+    AtlasPage {
+      image,
+      texture,
+      shelves: vec![],
+      dirty: false,
+    }
+  }
+
+  /** Top left of a free width x height area. Shelves are rows as high as their first sprite. */
+  fn allocate(&mut self, width: usize, height: usize) -> Option<(usize, usize)> {
+    for shelf in self.shelves.iter_mut() {
+      if height <= shelf.height && shelf.right + width <= ATLAS_PAGE_SIZE {
+        let x = shelf.right;
+        shelf.right += width;
+        return Some((x, shelf.top));
+      }
+    }
+    let top = self.shelves.last().map(|s| s.top + s.height).unwrap_or(0);
+    if top + height > ATLAS_PAGE_SIZE || width > ATLAS_PAGE_SIZE {
+      return None;
+    }
+    self.shelves.push(Shelf {
+      top,
+      height,
+      right: width,
+    });
+    Some((0, top))
+  }
+
+  fn blit(&mut self, image: &Image, x: usize, y: usize) {
+    let width = image.width();
+    let dst = self.image.get_image_data_mut();
+    image
+      .get_image_data()
+      .chunks(width)
+      .enumerate()
+      .for_each(|(row, src)| {
+        let start = (y + row) * ATLAS_PAGE_SIZE + x;
+        dst[start..start + width].copy_from_slice(src);
+      });
+    self.dirty = true;
+  }
+}
+
+/**
+Packs colourized sprites into a few large textures so tiles sharing a page can be drawn in
+one batch. Regions are never freed, dropped cache entries just leave their space unused.
+*/
+#[derive(Default)]
+pub struct Atlas {
+  pages: Vec<AtlasPage>,
+}
+
+impl Atlas {
+  pub fn insert(&mut self, image: &Image) -> AtlasRegion {
+    let (width, height) = (image.width() + PADDING * 2, image.height() + PADDING * 2);
+    let spot = self
+      .pages
+      .iter_mut()
+      .enumerate()
+      .find_map(|(i, page)| page.allocate(width, height).map(|xy| (i, xy)));
+    let (page, (x, y)) = match spot {
+      Some(spot) => spot,
+      None => {
+        let mut new_page = AtlasPage::new();
+        let xy = new_page.allocate(width, height).unwrap_or_else(|| {
+          panic!(
+            "Sprite of {}x{} does not fit in an atlas page.",
+            image.width(),
+            image.height()
+          )
+        });
+        self.pages.push(new_page);
+        (self.pages.len() - 1, xy)
+      }
+    };
+
+    let atlas_page = &mut self.pages[page];
+    atlas_page.blit(image, x + PADDING, y + PADDING);
+    AtlasRegion {
+      page,
+      texture: atlas_page.texture,
+      source: Rect::new(
+        (x + PADDING) as f32,
+        (y + PADDING) as f32,
+        image.width() as f32,
+        image.height() as f32,
+      ),
+    }
+  }
+
+  /** Uploads pages changed since the last flush. Call once a frame before it is shown. */
+  pub fn flush(&mut self) {
+    for page in self.pages.iter_mut().filter(|p| p.dirty) {
+      page.texture.update(&page.image);
+      page.dirty = false;
+    }
+  }
+
+  pub fn page_count(&self) -> usize {
+    self.pages.len()
+  }
+}
+
+//Page texture and the source and destination rects drawn from it.
+type PageDraws = (Texture2D, Vec<(Rect, Rect)>);
+
+/** Collects tile draws per atlas page so every page is submitted in one go. */
+#[derive(Default)]
+pub struct TileBatch {
+  pages: Vec<Option<PageDraws>>,
+}
+
+impl TileBatch {
+  pub fn push(&mut self, region: &AtlasRegion, dest: Rect) {
+    if self.pages.len() <= region.page {
+      self.pages.resize(region.page + 1, None);
+    }
+    self.pages[region.page]
+      .get_or_insert_with(|| (region.texture, vec![]))
+      .1
+      .push((region.source, dest));
+  }
+
+  pub fn submit(self) {
+    for (texture, draws) in self.pages.into_iter().flatten() {
+      for (source, dest) in draws {
+        draw_texture_ex(
+          texture,
+          dest.x,
+          dest.y,
+          WHITE,
+          DrawTextureParams {
+            dest_size: Some(vec2(dest.w, dest.h)),
+            source: Some(source),
+            ..Default::default()
+          },
+        );
+      }
+    }
+  }
+}
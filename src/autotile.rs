@@ -0,0 +1,244 @@
+use crate::{
+  sprite::{NineSliceDir, SpriteKey, SpriteNineGroup},
+  tile::Tile,
+};
+
+//Neighbour bits, clockwise from north.
+pub const N: u8 = 1;
+pub const NE: u8 = 2;
+pub const E: u8 = 4;
+pub const SE: u8 = 8;
+pub const S: u8 = 16;
+pub const SW: u8 = 32;
+pub const W: u8 = 64;
+pub const NW: u8 = 128;
+
+/** Number of distinct tiles in a blob tile set. */
+pub const BLOB_TILE_COUNT: usize = 47;
+/** Extended sheets hold the blob tiles 8 per row, see `BlobMask::index`. */
+pub const BLOB_SHEET_COLUMNS: usize = 8;
+pub const BLOB_SHEET_ROWS: usize = 6;
+
+const fn canonical(bits: u8) -> u8 {
+  let mut out = bits & (N | E | S | W);
+  //A corner only matters when both sides next to it are connected.
+  if bits & NE != 0 && bits & N != 0 && bits & E != 0 {
+    out |= NE;
+  }
+  if bits & SE != 0 && bits & S != 0 && bits & E != 0 {
+    out |= SE;
+  }
+  if bits & SW != 0 && bits & S != 0 && bits & W != 0 {
+    out |= SW;
+  }
+  if bits & NW != 0 && bits & N != 0 && bits & W != 0 {
+    out |= NW;
+  }
+  out
+}
+
+const fn blob_masks() -> [u8; BLOB_TILE_COUNT] {
+  let mut masks = [0; BLOB_TILE_COUNT];
+  let mut bits = 0;
+  let mut i = 0;
+  while bits < 256 {
+    if canonical(bits as u8) == bits as u8 {
+      masks[i] = bits as u8;
+      i += 1;
+    }
+    bits += 1;
+  }
+  masks
+}
+
+const fn blob_indexes() -> [u8; 256] {
+  let mut indexes = [0; 256];
+  let masks = blob_masks();
+  let mut i = 0;
+  while i < BLOB_TILE_COUNT {
+    indexes[masks[i] as usize] = i as u8;
+    i += 1;
+  }
+  indexes
+}
+
+const BLOB_MASKS: [u8; BLOB_TILE_COUNT] = blob_masks();
+const BLOB_INDEXES: [u8; 256] = blob_indexes();
+
+/**
+Which of the eight neighbours of a nine-group tile belong to the same group.
+Always canonical, so there are exactly `BLOB_TILE_COUNT` different masks.
+*/
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Eq)]
+pub struct BlobMask(u8);
+
+impl BlobMask {
+  pub const ISOLATED: BlobMask = BlobMask(0);
+
+  pub fn from_bits(bits: u8) -> BlobMask {
+    BlobMask(canonical(bits))
+  }
+  pub fn bits(&self) -> u8 {
+    self.0
+  }
+
+  /** Resolves the mask for the center of a row by row 3x3 block, see `World::get_surrounding`. */
+  pub fn from_surrounding(tiles: &[Option<&Tile>], nine_group: &SpriteNineGroup) -> BlobMask {
+    if tiles.len() != 9 {
+      return BlobMask::ISOLATED;
+    }
+    let same_group = |i: usize| match tiles[i].map(|t| t.get_sprite_key()) {
+      Some(SpriteKey::Nine(nine)) => &nine.sg == nine_group,
+      _ => false,
+    };
+    let bits = [
+      (1, N),
+      (2, NE),
+      (5, E),
+      (8, SE),
+      (7, S),
+      (6, SW),
+      (3, W),
+      (0, NW),
+    ]
+    .iter()
+    .filter(|(i, _)| same_group(*i))
+    .fold(0, |bits, (_, bit)| bits | bit);
+    BlobMask::from_bits(bits)
+  }
+
+  /** Position of the tile in an extended sheet, in ascending mask order. */
+  pub fn index(&self) -> usize {
+    BLOB_INDEXES[self.0 as usize] as usize
+  }
+  pub fn all() -> impl Iterator<Item = BlobMask> {
+    BLOB_MASKS.iter().map(|m| BlobMask(*m))
+  }
+
+  /** Closest piece on a classic 3x3 sheet. Inner corners, strips and single tiles have none. */
+  pub fn to_nine_slice_dir(self) -> NineSliceDir {
+    let has = |bit: u8| self.0 & bit != 0;
+    match (has(N), has(E), has(S), has(W)) {
+      (true, true, true, true) | (false, false, false, false) => NineSliceDir::Center,
+      (false, true, true, true) => NineSliceDir::Up,
+      (true, true, false, true) => NineSliceDir::Down,
+      (true, true, true, false) => NineSliceDir::Left,
+      (true, false, true, true) => NineSliceDir::Right,
+      (false, true, true, false) => NineSliceDir::LeftUp,
+      (false, false, true, true) => NineSliceDir::RightUp,
+      (true, true, false, false) => NineSliceDir::LeftDown,
+      (true, false, false, true) => NineSliceDir::RightDown,
+      //Horizontal strips and their ends.
+      (false, _, false, _) => NineSliceDir::Up,
+      //Vertical strips and their ends.
+      (_, false, _, false) => NineSliceDir::Left,
+    }
+  }
+  /** Inverse of `to_nine_slice_dir` for the nine classic pieces. */
+  pub fn from_nine_slice_dir(dir: NineSliceDir) -> BlobMask {
+    BlobMask::from_bits(match dir {
+      NineSliceDir::LeftUp => E | SE | S,
+      NineSliceDir::Up => E | SE | S | SW | W,
+      NineSliceDir::RightUp => S | SW | W,
+      NineSliceDir::Left => N | NE | E | SE | S,
+      NineSliceDir::Center => 255,
+      NineSliceDir::Right => N | S | SW | W | NW,
+      NineSliceDir::LeftDown => N | NE | E,
+      NineSliceDir::Down => N | NE | E | W | NW,
+      NineSliceDir::RightDown => N | W | NW,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    palette::PaletteName,
+    registry::init_test_registry,
+    sprite::{SpriteId, SpriteNinePaletteKey, SpritePaletteKey},
+  };
+
+  fn nine(x: usize, y: usize, sg: SpriteNineGroup) -> Tile {
+    let key = SpriteNinePaletteKey::new(
+      PaletteName::White,
+      PaletteName::Green,
+      sg,
+      0,
+      BlobMask::ISOLATED,
+    );
+    Tile::from_sprite_key(x, y, SpriteKey::Nine(key))
+  }
+  fn plain(x: usize, y: usize) -> Tile {
+    Tile::from_sprite_key(
+      x,
+      y,
+      SpriteKey::Specific(SpritePaletteKey::new(
+        SpriteId::EMPTY,
+        PaletteName::White,
+        PaletteName::White,
+      )),
+    )
+  }
+  //Row by row 3x3 block, '#' is the group, '.' another sprite and ' ' outside the world.
+  fn mask(rows: [&str; 3], sg: SpriteNineGroup) -> BlobMask {
+    let tiles: Vec<Option<Tile>> = rows
+      .iter()
+      .enumerate()
+      .flat_map(|(y, row)| {
+        row.chars().enumerate().map(move |(x, c)| match c {
+          '#' => Some(nine(x, y, sg)),
+          '.' => Some(plain(x, y)),
+          _ => None,
+        })
+      })
+      .collect();
+    let refs: Vec<Option<&Tile>> = tiles.iter().map(|t| t.as_ref()).collect();
+    BlobMask::from_surrounding(&refs, &sg)
+  }
+
+  #[test]
+  fn there_are_47_masks() {
+    assert_eq!(BlobMask::all().count(), BLOB_TILE_COUNT);
+    assert_eq!(BLOB_TILE_COUNT, 47);
+    for (i, mask) in BlobMask::all().enumerate() {
+      assert_eq!(mask.index(), i);
+    }
+  }
+
+  #[test]
+  fn corners_need_both_edges() {
+    assert_eq!(canonical(NE), 0);
+    assert_eq!(canonical(NE | N), N);
+    assert_eq!(canonical(NE | E), E);
+    assert_eq!(canonical(NE | N | E), NE | N | E);
+    assert_eq!(canonical(NE | SE | SW | NW), 0);
+    assert_eq!(canonical(255), 255);
+    assert_eq!(canonical(!N), E | SE | S | SW | W);
+  }
+
+  #[test]
+  fn resolves_from_surrounding_tiles() {
+    init_test_registry();
+    let trees: SpriteNineGroup = "Trees".parse().unwrap();
+    let mounds: SpriteNineGroup = "Mounds".parse().unwrap();
+
+    assert_eq!(mask(["...", ".#.", "..."], trees), BlobMask::ISOLATED);
+    assert_eq!(mask(["   ", " # ", "   "], trees), BlobMask::ISOLATED);
+    assert_eq!(mask(["###", "###", "###"], trees), BlobMask::from_bits(255));
+    assert_eq!(
+      mask(["...", "###", "###"], trees),
+      BlobMask::from_nine_slice_dir(NineSliceDir::Up)
+    );
+    //The north west corner is dropped without the north edge.
+    assert_eq!(mask(["#..", "##.", "..."], trees), BlobMask::from_bits(W));
+    //Other nine groups don't connect.
+    let mut tiles: Vec<Tile> = (0..9).map(|i| nine(i % 3, i / 3, trees)).collect();
+    tiles[1] = nine(1, 0, mounds);
+    let refs: Vec<Option<&Tile>> = tiles.iter().map(Some).collect();
+    assert_eq!(
+      BlobMask::from_surrounding(&refs, &trees),
+      BlobMask::from_bits(!N)
+    );
+  }
+}
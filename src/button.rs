@@ -1,15 +1,23 @@
 use macroquad::{
-  prelude::{is_mouse_button_pressed, mouse_position, Color, MouseButton, Vec2, PINK, WHITE},
+  prelude::{
+    is_mouse_button_pressed, mouse_position, Color, MouseButton, Vec2, PINK, WHITE, YELLOW,
+  },
+  shapes::{draw_rectangle, draw_rectangle_lines},
   text::{draw_text_ex, measure_text, TextParams},
-  texture::{draw_texture_ex, DrawTextureParams},
+  texture::{draw_texture_ex, DrawTextureParams, Texture2D},
 };
 
 use crate::{
   assets::Assets,
+  atlas::AtlasRegion,
+  autotile::BlobMask,
   debug::DebugState,
   palette::PaletteName,
   rect::Rect,
-  sprite::SpriteId,
+  sprite::{
+    SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteNineGroup, SpriteNinePaletteKey,
+    SpritePaletteKey,
+  },
   ui::{FontWeight, Fonts, Lang},
 };
 
@@ -17,12 +25,25 @@ const PALETTE_BUTTON_TEXT_SIZE: u16 = 22;
 const PALETTE_BUTTON_TEXT_WEIGHT: FontWeight = FontWeight::Bold;
 const BUTTON_TEXT_SIZE: u16 = 20;
 const BUTTON_TEXT_WEIGHT: FontWeight = FontWeight::Regular;
+const THUMBNAIL_FRAME: f32 = 2.0;
+const THUMBNAIL_FRAME_COLOR: Color = Color::new(0.2, 0.2, 0.25, 0.8);
+const THUMBNAIL_HOVER_COLOR: Color = Color::new(0.4, 0.4, 0.5, 0.9);
+const THUMBNAIL_SELECTED_COLOR: Color = YELLOW;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ButtonKind {
   Palette(PaletteName),
+  BackgroundPalette(PaletteName),
+  //Toggles taking the background from the surrounding tiles.
+  AutoBackground,
   Sprite(SpriteId),
-  SpriteGroup(),
+  //`None` picks the variant from the tile position.
+  SpriteGroup(SpriteGroup, Option<u8>),
+  SpriteNine(SpriteNineGroup, u8),
+  //Index into `World::layers`.
+  Layer(usize),
+  LayerVisible(usize),
+  LayerLocked(usize),
 }
 
 pub struct ButtonLabel {
@@ -46,7 +67,9 @@ impl FilledButtonLabel {
       weight: None,
     });
     match kind {
-      Some(ButtonKind::Palette(_)) => FilledButtonLabel {
+      Some(
+        ButtonKind::Palette(_) | ButtonKind::BackgroundPalette(_) | ButtonKind::AutoBackground,
+      ) => FilledButtonLabel {
         text: label.text,
         color: label.color.unwrap_or(WHITE),
         size: label.size.unwrap_or(PALETTE_BUTTON_TEXT_SIZE),
@@ -78,6 +101,26 @@ pub struct ButtonState {
   pub hovered: bool,
 }
 
+fn palette_texture(
+  assets: &Assets,
+  palette_name: &PaletteName,
+  selected: bool,
+  hovered: bool,
+) -> Texture2D {
+  let palette_texs = assets
+    .sprites
+    .palettes
+    .get(palette_name)
+    .unwrap_or_else(|| panic!("Palette texture not defined for `{:?}`.", palette_name));
+  if selected {
+    palette_texs.2
+  } else if hovered {
+    palette_texs.1
+  } else {
+    palette_texs.0
+  }
+}
+
 impl Button {
   pub fn new(
     fonts: &Fonts,
@@ -129,47 +172,87 @@ impl Button {
     }
   }
   pub fn bounds(&self) -> Rect {
-    self.bounds.clone()
+    self.bounds
   }
   pub fn kind(&self) -> &ButtonKind {
     &self.kind
   }
-  pub fn draw(&self, deb: &DebugState, assets: &Assets, selected: bool) -> ButtonState {
+  /** Moves the button so its bounds start at x,y. */
+  pub fn set_position(&mut self, x: usize, y: usize) {
+    let rect_offset = (
+      self.rect.left - self.bounds.left,
+      self.rect.top - self.bounds.top,
+    );
+    let text_offset = (
+      self.text_x - self.bounds.left as f32,
+      self.text_y - self.bounds.top as f32,
+    );
+    self.bounds = self.bounds.moved_to(x, y);
+    self.rect = self.rect.moved_to(x + rect_offset.0, y + rect_offset.1);
+    self.text_x = x as f32 + text_offset.0;
+    self.text_y = y as f32 + text_offset.1;
+  }
+  /** `palettes` are the foreground and background used for the thumbnails of sprite buttons. */
+  pub fn draw(
+    &self,
+    deb: &DebugState,
+    assets: &mut Assets,
+    selected: bool,
+    palettes: (PaletteName, PaletteName),
+  ) -> ButtonState {
     let m_pos = mouse_position();
 
-    let hovered = m_pos.0 >= self.rect.left as f32
-      && m_pos.0 <= self.rect.right as f32
-      && m_pos.1 >= self.rect.top as f32
-      && m_pos.1 <= self.rect.bottom as f32;
+    let hovered = self.rect.contains(&m_pos);
     let hit = hovered && is_mouse_button_pressed(MouseButton::Left);
+    let (fg, bg) = palettes;
 
-    let sprite = match self.kind {
-      ButtonKind::Palette(palette_name) => {
-        let palette_texs = assets
-          .sprites
-          .palettes
-          .get(&palette_name)
-          .unwrap_or_else(|| panic!("Palette texture not defined for `{:?}`.", palette_name));
-        if selected {
-          palette_texs.2
-        } else if hovered {
-          palette_texs.1
-        } else {
-          palette_texs.0
-        }
-      }
-      _ => {
-        panic!("Not implemented draw for button kind.")
+    //Sprites live in the atlas, palette buttons have textures of their own.
+    let in_atlas = |region: &AtlasRegion| (region.texture, Some(region.source));
+    let (texture, source) = match self.kind {
+      ButtonKind::Palette(palette_name) | ButtonKind::BackgroundPalette(palette_name) => (
+        palette_texture(assets, &palette_name, selected, hovered),
+        None,
+      ),
+      ButtonKind::AutoBackground | ButtonKind::Layer(_) => (
+        palette_texture(assets, &PaletteName::White, selected, hovered),
+        None,
+      ),
+      ButtonKind::LayerVisible(_) => (
+        palette_texture(assets, &PaletteName::Blue, selected, hovered),
+        None,
+      ),
+      ButtonKind::LayerLocked(_) => (
+        palette_texture(assets, &PaletteName::Red, selected, hovered),
+        None,
+      ),
+      ButtonKind::Sprite(sid) => {
+        in_atlas(assets.get_texture(&SpritePaletteKey::new(sid, fg, bg), true))
       }
+      ButtonKind::SpriteGroup(sg, sg_index) => in_atlas(assets.get_group_texture(
+        &SpriteGroupPaletteKey::new(fg, bg, sg, Some(sg_index.unwrap_or(0))),
+        true,
+      )),
+      ButtonKind::SpriteNine(sg, sg_index) => in_atlas(assets.get_nine_slice(
+        &SpriteNinePaletteKey::new(fg, bg, sg, sg_index, BlobMask::ISOLATED),
+        true,
+      )),
     };
 
+    if matches!(
+      self.kind,
+      ButtonKind::Sprite(_) | ButtonKind::SpriteGroup(_, _) | ButtonKind::SpriteNine(_, _)
+    ) {
+      self.draw_thumbnail_frame(selected, hovered);
+    }
+
     draw_texture_ex(
-      sprite,
+      texture,
       self.rect.left as f32,
       self.rect.top as f32,
       WHITE,
       DrawTextureParams {
         dest_size: Some(self.draw_destination_size),
+        source,
         ..Default::default()
       },
     );
@@ -193,6 +276,33 @@ impl Button {
     ButtonState { hit, hovered }
   }
 
+  fn draw_thumbnail_frame(&self, selected: bool, hovered: bool) {
+    let (x, y) = (self.rect.left as f32, self.rect.top as f32);
+    let (w, h) = (self.rect.width() as f32, self.rect.height() as f32);
+    let color = if hovered {
+      THUMBNAIL_HOVER_COLOR
+    } else {
+      THUMBNAIL_FRAME_COLOR
+    };
+    draw_rectangle(
+      x - THUMBNAIL_FRAME,
+      y - THUMBNAIL_FRAME,
+      w + THUMBNAIL_FRAME * 2.0,
+      h + THUMBNAIL_FRAME * 2.0,
+      color,
+    );
+    if selected {
+      draw_rectangle_lines(
+        x - THUMBNAIL_FRAME,
+        y - THUMBNAIL_FRAME,
+        w + THUMBNAIL_FRAME * 2.0,
+        h + THUMBNAIL_FRAME * 2.0,
+        THUMBNAIL_FRAME,
+        THUMBNAIL_SELECTED_COLOR,
+      );
+    }
+  }
+
   fn debug_draw(&self) {
     self.rect.debug_draw(Some(PINK));
     self.bounds.debug_draw(None);
@@ -0,0 +1,282 @@
+use macroquad::prelude::*;
+
+use crate::{
+  input::{is_ctrl_down, is_shift_down},
+  world::TILE_SIZE,
+};
+
+//Low enough for `fit` to show a `MAX_MAP_SIZE` map in a window 512 pixels high.
+pub const MIN_ZOOM: f32 = 1.0 / 32.0;
+pub const MAX_ZOOM: f32 = 8.0;
+//Free zoom changes by this factor per wheel notch or key press.
+const ZOOM_FACTOR: f32 = 1.1;
+//Scales where every sprite pixel covers a whole number of screen pixels, or the other way around.
+const PIXEL_PERFECT_ZOOMS: [f32; 13] = [
+  MIN_ZOOM, 0.0625, 0.125, 0.25, 0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0,
+];
+/** Screen pixels per second when panning with the keyboard. */
+const PAN_SPEED: f32 = 800.0;
+const FAST_PAN_SPEED: f32 = 2400.0;
+//How quickly the pan velocity reaches its target, higher is snappier.
+const PAN_EASING: f32 = 10.0;
+/** Screen pixels from the window edge where edge panning starts. */
+const EDGE_PAN_BORDER: f32 = 8.0;
+/** World pixels the camera may show outside the map. */
+const CAMERA_MARGIN: f32 = (TILE_SIZE * 4) as f32;
+
+/**
+Position and zoom of the view. `pos` is the world pixel at the top left corner of the screen,
+so moving right increases it.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+  pub pos: Vec2,
+  pub zoom: f32,
+  //Only step between `PIXEL_PERFECT_ZOOMS`.
+  pub pixel_perfect_zoom: bool,
+  //Pan when the mouse is at the window edge.
+  pub edge_panning: bool,
+  velocity: Vec2,
+  //`None` until the first update, so a camera can be made before the window exists.
+  prev_mouse_pos: Option<(f32, f32)>,
+}
+
+impl Camera {
+  /** `zoom` is clamped to `MIN_ZOOM..=MAX_ZOOM`. */
+  pub fn new(pos: Vec2, zoom: f32) -> Camera {
+    Camera {
+      pos,
+      zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+      pixel_perfect_zoom: false,
+      edge_panning: false,
+      velocity: Vec2::ZERO,
+      prev_mouse_pos: None,
+    }
+  }
+
+  /**
+  `map_size` is in world pixels, `mouse_over_ui` keeps the wheel for the panels under the cursor
+  and `keys_captured` the keyboard for an open dialog.
+  */
+  pub fn update(&mut self, map_size: Vec2, mouse_over_ui: bool, keys_captured: bool) {
+    self.update_panning(mouse_over_ui, keys_captured);
+    self.update_zoom(map_size, mouse_over_ui, keys_captured);
+    self.clamp(map_size);
+  }
+
+  /** Screen position of a world pixel position. */
+  pub fn world_to_screen(&self, world: Vec2) -> Vec2 {
+    (world - self.draw_pos()) * self.zoom
+  }
+  pub fn screen_to_world(&self, screen: (f32, f32)) -> Vec2 {
+    vec2(screen.0, screen.1) / self.zoom + self.draw_pos()
+  }
+  //Whole screen pixels so tiles do not shimmer while panning.
+  fn draw_pos(&self) -> Vec2 {
+    (self.pos * self.zoom).round() / self.zoom
+  }
+
+  /** Size of the screen in world pixels. */
+  pub fn view_size(&self) -> Vec2 {
+    vec2(screen_width(), screen_height()) / self.zoom
+  }
+
+  /** Puts a world pixel position in the middle of the screen. */
+  pub fn center_on(&mut self, world: Vec2) {
+    self.pos = world - self.view_size() / 2.0;
+    self.velocity = Vec2::ZERO;
+  }
+
+  /** Sets the zoom while keeping the world position under the screen position `pos` in place. */
+  pub fn zoom_at(&mut self, zoom: f32, pos: (f32, f32)) {
+    let world = self.screen_to_world(pos);
+    self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    self.pos = world - vec2(pos.0, pos.1) / self.zoom;
+  }
+
+  /** Zoom one step in (`steps` > 0) or out from the current zoom. */
+  pub fn stepped_zoom(&self, steps: i32) -> f32 {
+    if !self.pixel_perfect_zoom {
+      return self.zoom * ZOOM_FACTOR.powi(steps);
+    }
+    //A little slack so a free zoom close to a level does not step to that same level.
+    let slack = 0.001;
+    let mut zoom = self.zoom;
+    for _ in 0..steps.abs() {
+      zoom = if steps > 0 {
+        PIXEL_PERFECT_ZOOMS
+          .iter()
+          .find(|z| **z > zoom + slack)
+          .copied()
+          .unwrap_or(MAX_ZOOM)
+      } else {
+        PIXEL_PERFECT_ZOOMS
+          .iter()
+          .rev()
+          .find(|z| **z < zoom - slack)
+          .copied()
+          .unwrap_or(MIN_ZOOM)
+      };
+    }
+    zoom
+  }
+
+  /** Toggles pixel perfect zoom, snapping to the closest level when turned on. */
+  pub fn toggle_pixel_perfect_zoom(&mut self) {
+    self.pixel_perfect_zoom = !self.pixel_perfect_zoom;
+    if self.pixel_perfect_zoom {
+      let closest = PIXEL_PERFECT_ZOOMS
+        .iter()
+        .min_by(|a, b| (*a - self.zoom).abs().total_cmp(&(*b - self.zoom).abs()))
+        .copied()
+        .unwrap_or(1.0);
+      self.zoom_at(closest, screen_center());
+    }
+  }
+
+  /** Zooms so a map of `map_size` world pixels fits the window and centers it. */
+  pub fn fit(&mut self, map_size: Vec2) {
+    let mut zoom = (screen_width() / map_size.x).min(screen_height() / map_size.y);
+    if self.pixel_perfect_zoom {
+      zoom = PIXEL_PERFECT_ZOOMS
+        .iter()
+        .rev()
+        .find(|z| **z <= zoom)
+        .copied()
+        .unwrap_or(MIN_ZOOM);
+    }
+    self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    self.center_on(map_size / 2.0);
+  }
+
+  /** Keeps the map, plus `CAMERA_MARGIN`, on screen. Maps smaller than the screen are centered. */
+  pub fn clamp(&mut self, map_size: Vec2) {
+    let view = self.view_size();
+    let clamp_axis = |pos: f32, map: f32, view: f32| {
+      let (min, max) = (-CAMERA_MARGIN, map + CAMERA_MARGIN - view);
+      if min > max {
+        (map - view) / 2.0
+      } else {
+        pos.clamp(min, max)
+      }
+    };
+    let clamped = vec2(
+      clamp_axis(self.pos.x, map_size.x, view.x),
+      clamp_axis(self.pos.y, map_size.y, view.y),
+    );
+    //Stop pushing against the edge so panning away again is immediate.
+    if clamped.x != self.pos.x {
+      self.velocity.x = 0.0;
+    }
+    if clamped.y != self.pos.y {
+      self.velocity.y = 0.0;
+    }
+    self.pos = clamped;
+  }
+
+  fn update_panning(&mut self, mouse_over_ui: bool, keys_captured: bool) {
+    let mouse_pos = mouse_position();
+    let prev = self.prev_mouse_pos.unwrap_or(mouse_pos);
+    let mouse_diff = vec2(mouse_pos.0 - prev.0, mouse_pos.1 - prev.1);
+    self.prev_mouse_pos = Some(mouse_pos);
+    if is_mouse_button_down(MouseButton::Right) {
+      self.pos -= mouse_diff / self.zoom;
+      self.velocity = Vec2::ZERO;
+      return;
+    }
+
+    let mut dir = Vec2::ZERO;
+    if !is_ctrl_down() && !keys_captured {
+      dir.x = key_axis(
+        is_key_down(KeyCode::A) || is_key_down(KeyCode::Left),
+        is_key_down(KeyCode::D) || is_key_down(KeyCode::Right),
+      );
+      dir.y = key_axis(
+        is_key_down(KeyCode::W) || is_key_down(KeyCode::Up),
+        is_key_down(KeyCode::S) || is_key_down(KeyCode::Down),
+      );
+    }
+    if self.edge_panning && !mouse_over_ui && dir == Vec2::ZERO {
+      dir.x = key_axis(
+        mouse_pos.0 < EDGE_PAN_BORDER,
+        mouse_pos.0 > screen_width() - EDGE_PAN_BORDER,
+      );
+      dir.y = key_axis(
+        mouse_pos.1 < EDGE_PAN_BORDER,
+        mouse_pos.1 > screen_height() - EDGE_PAN_BORDER,
+      );
+    }
+
+    let speed = if is_shift_down() && !keys_captured {
+      FAST_PAN_SPEED
+    } else {
+      PAN_SPEED
+    };
+    let target = dir.normalize_or_zero() * speed;
+    let dt = get_frame_time();
+    self.velocity += (target - self.velocity) * (PAN_EASING * dt).min(1.0);
+    if target == Vec2::ZERO && self.velocity.length() < 1.0 {
+      self.velocity = Vec2::ZERO;
+    }
+    self.pos += self.velocity * dt / self.zoom;
+  }
+
+  fn update_zoom(&mut self, map_size: Vec2, mouse_over_ui: bool, keys_captured: bool) {
+    let wheel = mouse_wheel().1;
+    if wheel != 0.0 && !mouse_over_ui {
+      let zoom = self.stepped_zoom(wheel.signum() as i32);
+      self.zoom_at(zoom, mouse_position());
+    }
+    if is_ctrl_down() || keys_captured {
+      return;
+    }
+    //Keys zoom at the cursor like the wheel while it is over the map.
+    let world_mouse = self.screen_to_world(mouse_position());
+    let over_map = world_mouse.cmpge(Vec2::ZERO).all() && world_mouse.cmplt(map_size).all();
+    let key_zoom_pos = if over_map && !mouse_over_ui {
+      mouse_position()
+    } else {
+      screen_center()
+    };
+    if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::KpAdd) {
+      self.zoom_at(self.stepped_zoom(1), key_zoom_pos);
+    }
+    if is_key_pressed(KeyCode::Minus) || is_key_pressed(KeyCode::KpSubtract) {
+      self.zoom_at(self.stepped_zoom(-1), key_zoom_pos);
+    }
+  }
+}
+
+//-1 for the negative direction, 1 for the positive and 0 for neither or both.
+fn key_axis(negative: bool, positive: bool) -> f32 {
+  match (negative, positive) {
+    (true, false) => -1.0,
+    (false, true) => 1.0,
+    _ => 0.0,
+  }
+}
+
+fn screen_center() -> (f32, f32) {
+  (screen_width() / 2.0, screen_height() / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::world::MAX_MAP_SIZE;
+
+  #[test]
+  fn min_zoom_fits_the_largest_map() {
+    let side = (MAX_MAP_SIZE * TILE_SIZE) as f32;
+    assert!(side * MIN_ZOOM <= 512.0);
+  }
+
+  #[test]
+  fn pixel_perfect_steps_reach_min_zoom() {
+    let mut camera = Camera::new(Vec2::ZERO, 1.0);
+    camera.pixel_perfect_zoom = true;
+    assert_eq!(camera.stepped_zoom(-5), MIN_ZOOM);
+    assert_eq!(camera.stepped_zoom(-20), MIN_ZOOM);
+    assert_eq!(camera.stepped_zoom(-1), 0.5);
+  }
+}
@@ -1,8 +1,10 @@
 use macroquad::{prelude::*, time};
 
-use crate::{assets::Assets, ui::Lang, world::World};
+use crate::{assets::Assets, history::History, input::is_ctrl_down, ui::Lang, world::World};
 
 const TEXT_ROW_HEIGHT: f32 = 18.0;
+/** Seconds a toast stays on screen. */
+const TOAST_DURATION: f64 = 4.0;
 
 #[derive(Debug)]
 pub struct DebugState {
@@ -11,37 +13,48 @@ pub struct DebugState {
   pub cursor_pos: bool,
   pub cursor_tile: bool,
   pub enabled: bool,
+  pub history: bool,
   pub invert_text_color: bool,
   pub tiles: bool,
+  //Message and the time it was shown.
+  pub toast: Option<(String, f64)>,
 }
 
 impl Default for DebugState {
   fn default() -> Self {
     DebugState {
-      allow_missing_sprites: true,
+      allow_missing_sprites: false,
       buttons: false,
       cursor_pos: true,
       cursor_tile: true,
       enabled: false,
+      history: false,
       invert_text_color: false,
       tiles: false,
+      toast: None,
     }
   }
 }
 
 impl DebugState {
-  pub fn draw_texts(&self, assets: &Assets, world: &World) {
+  pub fn show_toast(&mut self, text: String) {
+    println!("{}", text);
+    self.toast = Some((text, get_time()));
+  }
+
+  pub fn draw_texts(&self, assets: &Assets, world: &World, history: &History) {
     let color = if self.invert_text_color { WHITE } else { BLACK };
     if self.enabled {
       draw_text_ex(
         format!(
-          "fps:{:0>2} ({:.3})  ft:{:0>3}ms",
+          "fps:{:0>2} ({:.3})  ft:{:0>3}ms  atlas:{}",
           time::get_fps(),
           time::get_frame_time(),
           ((get_time() - assets.frame_beginning) * 1000.0).round() as usize,
+          assets.atlas_page_count(),
         )
         .as_str(),
-        screen_width() - 120.0,
+        screen_width() - 160.0,
         8.0,
         TextParams {
           font: assets
@@ -67,8 +80,25 @@ impl DebugState {
     if self.enabled && self.cursor_pos {
       draw_text_ex(
         format!(
-          "scroll:{:?}    mouse:{:?}    w_px:{:?}    grid:{:?}",
-          world.scroll_pos, world.mouse_pos, world.mouse_world_px, world.mouse_grid
+          "camera:{:?}    mouse:{:?}    w_px:{:?}    grid:{:?}",
+          world.camera.pos, world.mouse_pos, world.mouse_world_px, world.mouse_grid
+        )
+        .as_str(),
+        20.0,
+        text_y,
+        params,
+      );
+      text_y += TEXT_ROW_HEIGHT;
+    }
+    if self.enabled {
+      //Every visible layer draws its own tiles.
+      let visible_layers = world.layers().iter().filter(|l| l.visible).count();
+      draw_text_ex(
+        format!(
+          "tiles drawn:{}/{} on {} layers",
+          world.drawn_tiles,
+          world.width * world.height * visible_layers,
+          visible_layers
         )
         .as_str(),
         20.0,
@@ -83,14 +113,41 @@ impl DebugState {
         t = world.get_tile(&grid.0, &grid.1);
       }
       draw_text_ex(format!("mouse_tile:{:?}", t).as_str(), 20.0, text_y, params);
-      // text_y += TEXT_ROW_HEIGHT;
+      text_y += TEXT_ROW_HEIGHT;
+    }
+    if self.enabled && self.history {
+      draw_text_ex("history:", 20.0, text_y, params);
+      text_y += TEXT_ROW_HEIGHT;
+      for entry in history.redo_entries().rev() {
+        draw_text_ex(
+          format!("    (redo) {} [{}]", entry.label, entry.changes.len()).as_str(),
+          20.0,
+          text_y,
+          params,
+        );
+        text_y += TEXT_ROW_HEIGHT;
+      }
+      for (i, entry) in history.undo_entries().enumerate().rev() {
+        draw_text_ex(
+          format!("{:>4} {} [{}]", i, entry.label, entry.changes.len()).as_str(),
+          20.0,
+          text_y,
+          params,
+        );
+        text_y += TEXT_ROW_HEIGHT;
+      }
+    }
+    if let Some((text, shown)) = &self.toast {
+      if self.enabled && get_time() - shown < TOAST_DURATION {
+        draw_text_ex(text, 20.0, screen_height() - TEXT_ROW_HEIGHT, params);
+      }
     }
   }
 }
 
 pub fn update_debug(state: &mut DebugState) {
   if is_key_pressed(KeyCode::F1) {
-    if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::LeftControl) {
+    if is_ctrl_down() {
       state.invert_text_color = !state.invert_text_color;
       return;
     }
@@ -105,4 +162,7 @@ pub fn update_debug(state: &mut DebugState) {
   if is_key_pressed(KeyCode::F6) {
     state.buttons = !state.buttons;
   }
+  if is_key_pressed(KeyCode::F7) {
+    state.history = !state.history;
+  }
 }
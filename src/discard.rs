@@ -0,0 +1,81 @@
+use macroquad::{
+  hash,
+  prelude::*,
+  ui::{root_ui, widgets},
+};
+
+const PROMPT_WIDTH: f32 = 340.0;
+const PROMPT_HEIGHT: f32 = 110.0;
+
+/** What replaces the world once its unsaved edits are given up. */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WorldReplacement {
+  //World layers changed on disk, see `AssetWatcher`.
+  Reload,
+  //The saved map, Ctrl+O.
+  Open,
+  //A blank world from the `NewMapDialog`.
+  New,
+}
+
+impl WorldReplacement {
+  fn question(&self) -> &'static str {
+    match self {
+      WorldReplacement::Reload => "The world changed on disk. Reload it?",
+      WorldReplacement::Open => "Open the saved map?",
+      WorldReplacement::New => "Create the new map?",
+    }
+  }
+  fn action(&self) -> &'static str {
+    match self {
+      WorldReplacement::Reload => "Reload",
+      WorldReplacement::Open => "Open",
+      WorldReplacement::New => "Create",
+    }
+  }
+}
+
+/** Asks before the world and its history are replaced while there are unsaved edits. */
+#[derive(Default)]
+pub struct DiscardPrompt {
+  pending: Option<WorldReplacement>,
+}
+
+impl DiscardPrompt {
+  pub fn show(&mut self, replacement: WorldReplacement) {
+    self.pending = Some(replacement);
+  }
+  pub fn is_open(&self) -> bool {
+    self.pending.is_some()
+  }
+
+  /** Draws the prompt while it is open. Returns the replacement once it is confirmed. */
+  pub fn draw(&mut self) -> Option<WorldReplacement> {
+    let replacement = self.pending?;
+    let mut confirmed = false;
+    let mut keep = false;
+    let pos = vec2(
+      (screen_width() - PROMPT_WIDTH) / 2.0,
+      (screen_height() - PROMPT_HEIGHT) / 2.0,
+    );
+    widgets::Window::new(hash!(), pos, vec2(PROMPT_WIDTH, PROMPT_HEIGHT))
+      .label("Unsaved edits")
+      .movable(false)
+      .ui(&mut root_ui(), |ui| {
+        ui.label(None, replacement.question());
+        ui.label(None, "The unsaved edits and their undo history are lost.");
+        confirmed = ui.button(None, replacement.action());
+        ui.same_line(0.0);
+        keep = ui.button(None, "Keep edits") || is_key_pressed(KeyCode::Escape);
+      });
+    if confirmed || keep {
+      self.pending = None;
+    }
+    confirmed.then_some(replacement)
+  }
+
+  /** Whether the screen position is over the prompt. */
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    self.is_open() && root_ui().is_mouse_over(vec2(pos.0, pos.1))
+  }
+}
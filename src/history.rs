@@ -0,0 +1,354 @@
+use std::collections::VecDeque;
+
+use hashbrown::HashMap;
+
+use crate::{
+  sprite::SpriteKey,
+  world::{Anchor, World, WorldSnapshot},
+};
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct TileChange {
+  pub layer: usize,
+  pub x: usize,
+  pub y: usize,
+  pub before: Option<SpriteKey>,
+  pub after: Option<SpriteKey>,
+}
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+  pub label: &'static str,
+  pub changes: Vec<TileChange>,
+  //The whole world before and after edits that move cells, see `History::resize`.
+  pub resize: Option<(WorldSnapshot, WorldSnapshot)>,
+}
+
+/** An entry that is still being recorded, e.g. while the mouse button is held. */
+struct Stroke {
+  entry: HistoryEntry,
+  //Index into entry.changes per layer and cell, so a cell painted twice is stored once.
+  cells: HashMap<(usize, usize, usize), usize>,
+}
+
+/** Undo/redo stacks for every mutation of the world's tiles. */
+pub struct History {
+  undo: VecDeque<HistoryEntry>,
+  redo: Vec<HistoryEntry>,
+  stroke: Option<Stroke>,
+  depth: usize,
+  //Whether the world changed since it was loaded or saved.
+  unsaved: bool,
+}
+
+impl History {
+  pub fn new(depth: usize) -> History {
+    History {
+      undo: VecDeque::new(),
+      redo: Vec::new(),
+      stroke: None,
+      depth: depth.max(1),
+      unsaved: false,
+    }
+  }
+
+  /** Collects every following `set_tiles` into one entry until `end_stroke` is called. */
+  pub fn begin_stroke(&mut self, label: &'static str) {
+    self.end_stroke();
+    self.stroke = Some(Stroke {
+      entry: HistoryEntry {
+        label,
+        changes: Vec::new(),
+        resize: None,
+      },
+      cells: HashMap::new(),
+    });
+  }
+  pub fn in_stroke(&self) -> bool {
+    self.stroke.is_some()
+  }
+  pub fn end_stroke(&mut self) {
+    if let Some(mut stroke) = self.stroke.take() {
+      //Cells changed and changed back, e.g. by a cancelled move.
+      stroke.entry.changes.retain(|c| c.before != c.after);
+      self.push(stroke.entry);
+    }
+  }
+
+  /**
+  Changes several cells of the active layer through the history, then re-resolves the
+  nine-slice tiles around them. Outside a stroke every call becomes its own entry.
+  Neighbours touched by that are recorded in the same entry so undo restores their edges too.
+  */
+  pub fn set_tiles(&mut self, world: &mut World, tiles: Vec<((usize, usize), Option<SpriteKey>)>) {
+    let layer = world.active_layer();
+    let mut changes = Vec::with_capacity(tiles.len());
+    for ((x, y), key) in tiles {
+      let before = world.set_sprite_key(layer, &x, &y, key.clone());
+      if before != key {
+        changes.push(TileChange {
+          layer,
+          x,
+          y,
+          before,
+          after: key,
+        });
+      }
+    }
+    if changes.is_empty() {
+      return;
+    }
+
+    let cells: Vec<(usize, usize)> = changes.iter().map(|c| (c.x, c.y)).collect();
+    for ((x, y), before) in world.resolve_nine_slices_around(layer, &cells) {
+      changes.push(TileChange {
+        layer,
+        x,
+        y,
+        before: Some(before),
+        after: world
+          .get_layer_tile(layer, &x, &y)
+          .map(|t| t.get_sprite_key().clone()),
+      });
+    }
+    self.record(changes);
+  }
+  pub fn undo(&mut self, world: &mut World) -> bool {
+    self.end_stroke();
+    let Some(entry) = self.undo.pop_back() else {
+      return false;
+    };
+    if let Some((before, _)) = &entry.resize {
+      world.restore(before);
+    }
+    for change in entry.changes.iter().rev() {
+      world.set_sprite_key(change.layer, &change.x, &change.y, change.before.clone());
+    }
+    self.redo.push(entry);
+    self.unsaved = true;
+    true
+  }
+  pub fn redo(&mut self, world: &mut World) -> bool {
+    self.end_stroke();
+    let Some(entry) = self.redo.pop() else {
+      return false;
+    };
+    if let Some((_, after)) = &entry.resize {
+      world.restore(after);
+    }
+    for change in entry.changes.iter() {
+      world.set_sprite_key(change.layer, &change.x, &change.y, change.after.clone());
+    }
+    self.undo.push_back(entry);
+    self.unsaved = true;
+    true
+  }
+
+  /** Resizes the world as one entry, undo brings back the cells that were cut off. */
+  pub fn resize(
+    &mut self,
+    world: &mut World,
+    width: usize,
+    height: usize,
+    anchor: Anchor,
+    fill: Option<SpriteKey>,
+  ) {
+    self.end_stroke();
+    let before = world.snapshot();
+    world.resize(width, height, anchor, fill);
+    if (world.width, world.height) == (before.width(), before.height()) {
+      return;
+    }
+    self.push(HistoryEntry {
+      label: "Resize",
+      changes: Vec::new(),
+      resize: Some((before, world.snapshot())),
+    });
+  }
+
+  /** Forgets everything, used when the world is replaced. */
+  pub fn clear(&mut self) {
+    self.undo.clear();
+    self.redo.clear();
+    self.stroke = None;
+    self.unsaved = false;
+  }
+  pub fn mark_saved(&mut self) {
+    self.unsaved = false;
+  }
+  /** Whether replacing the world would lose edits. Undoing back to the saved state still counts. */
+  pub fn has_unsaved_edits(&self) -> bool {
+    self.unsaved
+  }
+
+  /** Undo stack, oldest first. */
+  pub fn undo_entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + ExactSizeIterator {
+    self.undo.iter()
+  }
+  /** Redo stack, next redo first. */
+  pub fn redo_entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + ExactSizeIterator {
+    self.redo.iter().rev()
+  }
+
+  fn record(&mut self, changes: Vec<TileChange>) {
+    let single_edit = self.stroke.is_none();
+    if single_edit {
+      self.begin_stroke("Edit tiles");
+    }
+    if let Some(stroke) = &mut self.stroke {
+      for change in changes {
+        let cell = (change.layer, change.x, change.y);
+        if let Some(i) = stroke.cells.get(&cell) {
+          stroke.entry.changes[*i].after = change.after;
+        } else {
+          stroke.cells.insert(cell, stroke.entry.changes.len());
+          stroke.entry.changes.push(change);
+        }
+      }
+    }
+    if single_edit {
+      self.end_stroke();
+    }
+  }
+  fn push(&mut self, entry: HistoryEntry) {
+    if entry.changes.is_empty() && entry.resize.is_none() {
+      return;
+    }
+    self.redo.clear();
+    self.undo.push_back(entry);
+    self.unsaved = true;
+    while self.undo.len() > self.depth {
+      self.undo.pop_front();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    palette::PaletteName,
+    registry::init_test_registry,
+    sprite::{SpriteId, SpritePaletteKey},
+  };
+
+  fn key(foreground: PaletteName) -> SpriteKey {
+    SpriteKey::Specific(SpritePaletteKey::new(
+      SpriteId::EMPTY,
+      foreground,
+      PaletteName::Green,
+    ))
+  }
+  fn world() -> World {
+    init_test_registry();
+    World::blank(3, 3, &key(PaletteName::White))
+  }
+  fn key_at(world: &World, x: usize, y: usize) -> SpriteKey {
+    world.get_tile(&x, &y).unwrap().get_sprite_key().clone()
+  }
+
+  #[test]
+  fn undo_and_redo_restore_tiles() {
+    let mut world = world();
+    let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+    history.set_tiles(&mut world, vec![((1, 1), Some(key(PaletteName::Red)))]);
+    history.set_tiles(&mut world, vec![((2, 1), None)]);
+
+    assert!(history.undo(&mut world));
+    assert!(world.get_tile(&2, &1).is_some());
+    assert_eq!(key_at(&world, 1, 1), key(PaletteName::Red));
+    assert!(history.undo(&mut world));
+    assert_eq!(key_at(&world, 1, 1), key(PaletteName::White));
+    assert!(!history.undo(&mut world));
+
+    assert!(history.redo(&mut world));
+    assert!(history.redo(&mut world));
+    assert_eq!(key_at(&world, 1, 1), key(PaletteName::Red));
+    assert!(world.get_tile(&2, &1).is_none());
+    assert!(!history.redo(&mut world));
+  }
+
+  #[test]
+  fn a_stroke_is_one_entry() {
+    let mut world = world();
+    let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+    history.begin_stroke("Brush");
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::Red)))]);
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::Blue)))]);
+    history.set_tiles(&mut world, vec![((1, 0), Some(key(PaletteName::Blue)))]);
+    history.end_stroke();
+
+    assert_eq!(history.undo_entries().len(), 1);
+    let entry = history.undo_entries().next().unwrap();
+    assert_eq!(entry.label, "Brush");
+    //A cell painted twice keeps its first before and its last after.
+    assert_eq!(entry.changes.len(), 2);
+    assert_eq!(entry.changes[0].before, Some(key(PaletteName::White)));
+    assert_eq!(entry.changes[0].after, Some(key(PaletteName::Blue)));
+
+    history.undo(&mut world);
+    assert_eq!(key_at(&world, 0, 0), key(PaletteName::White));
+    assert_eq!(key_at(&world, 1, 0), key(PaletteName::White));
+  }
+
+  #[test]
+  fn old_entries_are_dropped_past_the_depth() {
+    let mut world = world();
+    let mut history = History::new(2);
+    for (i, pn) in [PaletteName::Red, PaletteName::Blue, PaletteName::Brown]
+      .into_iter()
+      .enumerate()
+    {
+      history.set_tiles(&mut world, vec![((i, 0), Some(key(pn)))]);
+    }
+    assert_eq!(history.undo_entries().len(), 2);
+    assert!(history.undo(&mut world));
+    assert!(history.undo(&mut world));
+    assert!(!history.undo(&mut world));
+    //The oldest edit can no longer be undone.
+    assert_eq!(key_at(&world, 0, 0), key(PaletteName::Red));
+  }
+
+  #[test]
+  fn a_new_edit_clears_redo() {
+    let mut world = world();
+    let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::Red)))]);
+    history.undo(&mut world);
+    assert_eq!(history.redo_entries().len(), 1);
+    history.set_tiles(&mut world, vec![((1, 1), Some(key(PaletteName::Blue)))]);
+    assert_eq!(history.redo_entries().len(), 0);
+    assert!(!history.redo(&mut world));
+  }
+
+  #[test]
+  fn edits_changed_back_leave_no_entry() {
+    let mut world = world();
+    let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+    history.begin_stroke("Move");
+    history.set_tiles(&mut world, vec![((0, 0), None)]);
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::White)))]);
+    history.end_stroke();
+    assert_eq!(history.undo_entries().len(), 0);
+  }
+
+  #[test]
+  fn tracks_unsaved_edits() {
+    let mut world = world();
+    let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+    assert!(!history.has_unsaved_edits());
+    //Painting a cell with what it already holds changes nothing.
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::White)))]);
+    assert!(!history.has_unsaved_edits());
+    history.set_tiles(&mut world, vec![((0, 0), Some(key(PaletteName::Red)))]);
+    assert!(history.has_unsaved_edits());
+    history.mark_saved();
+    assert!(!history.has_unsaved_edits());
+    history.undo(&mut world);
+    assert!(history.has_unsaved_edits());
+    history.clear();
+    assert!(!history.has_unsaved_edits());
+  }
+}
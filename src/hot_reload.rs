@@ -0,0 +1,96 @@
+use std::{fs, time::SystemTime};
+
+use hashbrown::HashMap;
+use macroquad::time::get_time;
+
+use crate::{
+  loading,
+  sprite::{SpriteGroup, SpriteId, SpriteNineGroup},
+  world::DEFAULT_LAYERS,
+};
+
+/** Seconds between checking the watched files. */
+const POLL_INTERVAL: f64 = 1.0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ChangedAsset {
+  Sprite(SpriteId),
+  Group(SpriteGroup),
+  Nine(SpriteNineGroup),
+  World,
+}
+
+/** Polls the modification times of the sprite frames and the world layer pngs. */
+pub struct AssetWatcher {
+  files: Vec<(String, ChangedAsset)>,
+  mtimes: HashMap<String, Option<SystemTime>>,
+  last_poll: f64,
+}
+
+impl AssetWatcher {
+  pub fn new(world_name: &str) -> AssetWatcher {
+    let mut files: Vec<(String, ChangedAsset)> = DEFAULT_LAYERS
+      .iter()
+      .enumerate()
+      .map(|(i, layer)| {
+        (
+          loading::layer_path(world_name, i, layer),
+          ChangedAsset::World,
+        )
+      })
+      .collect();
+    let mut watch = |paths: Vec<String>, asset: ChangedAsset| {
+      files.extend(paths.into_iter().map(|p| (p, asset)));
+    };
+    for sid in SpriteId::iter() {
+      watch(
+        loading::sprite_frame_paths(sid.def()),
+        ChangedAsset::Sprite(sid),
+      );
+    }
+    for sg in SpriteGroup::iter() {
+      watch(
+        loading::sprite_frame_paths(sg.def()),
+        ChangedAsset::Group(sg),
+      );
+    }
+    for sg in SpriteNineGroup::iter() {
+      watch(
+        loading::sprite_frame_paths(sg.def()),
+        ChangedAsset::Nine(sg),
+      );
+    }
+
+    let mtimes = files
+      .iter()
+      .map(|(path, _)| (path.clone(), modified(path)))
+      .collect();
+    AssetWatcher {
+      files,
+      mtimes,
+      last_poll: get_time(),
+    }
+  }
+
+  /** Assets whose files were changed, added or removed since the last poll. */
+  pub fn poll(&mut self) -> Vec<ChangedAsset> {
+    let now = get_time();
+    if now - self.last_poll < POLL_INTERVAL {
+      return vec![];
+    }
+    self.last_poll = now;
+
+    let mut changed = vec![];
+    for (path, asset) in self.files.iter() {
+      let mtime = modified(path);
+      if self.mtimes.insert(path.clone(), mtime) != Some(mtime) && !changed.contains(asset) {
+        changed.push(*asset);
+      }
+    }
+    changed
+  }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|m| m.modified()).ok()
+}
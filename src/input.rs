@@ -0,0 +1,11 @@
+use macroquad::prelude::{is_key_down, KeyCode};
+
+pub fn is_ctrl_down() -> bool {
+  is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl)
+}
+pub fn is_shift_down() -> bool {
+  is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift)
+}
+pub fn is_alt_down() -> bool {
+  is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt)
+}
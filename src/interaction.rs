@@ -1,11 +1,12 @@
-use macroquad::prelude::{is_mouse_button_pressed, MouseButton};
-
 use crate::{
+  autotile::BlobMask,
+  button::ButtonKind,
+  history::History,
   math::mode,
   palette::PaletteName,
   sprite::{
-    NineSliceDir, SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNineGroup,
-    SpriteNinePaletteKey, SpritePaletteKey,
+    SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNineGroup, SpriteNinePaletteKey,
+    SpritePaletteKey,
   },
   tile::Tile,
   world::World,
@@ -18,14 +19,14 @@ use crate::{
 //   left: Option<SpriteId>,
 // }
 
-fn get_surrounding_bg(surrounding: &Vec<Option<&Tile>>) -> PaletteName {
+fn get_surrounding_bg(surrounding: &[Option<&Tile>]) -> PaletteName {
   let surrounding_palettes: Vec<u8> = surrounding
     .iter()
     .map(|t| {
       let Some(tile) = t else {
-          return PaletteName::Green as u8;
-        };
-      return tile.get_sprite_key().get_bg().clone() as u8;
+        return PaletteName::Green as u8;
+      };
+      *tile.get_sprite_key().get_bg() as u8
     })
     .collect();
   //Get most common u8 from palettes.
@@ -44,7 +45,7 @@ pub struct SelectionNine {
 }
 pub struct SelectionGroup {
   group: SpriteGroup,
-  index: u8,
+  index: Option<u8>,
 }
 
 pub struct Selections {
@@ -52,27 +53,158 @@ pub struct Selections {
   group: Option<SelectionGroup>,
   nine: Option<SelectionNine>,
   foreground: PaletteName,
-  background: Option<PaletteName>,
+  background: PaletteName,
+  //Take the background from the surrounding tiles instead of `background`.
+  auto_background: bool,
 }
 impl Selections {
+  pub fn new(foreground: PaletteName, background: PaletteName) -> Selections {
+    Selections {
+      sid: None,
+      group: None,
+      nine: None,
+      foreground,
+      background,
+      auto_background: true,
+    }
+  }
+
+  pub fn foreground(&self) -> PaletteName {
+    self.foreground
+  }
+  pub fn background(&self) -> PaletteName {
+    self.background
+  }
+  /** Background for the tile at x,y, the most common one around it when auto background is on. */
+  pub fn background_at(&self, world: &World, tile_x: &usize, tile_y: &usize) -> PaletteName {
+    if self.auto_background {
+      get_surrounding_bg(&world.get_surrounding(tile_x, tile_y))
+    } else {
+      self.background
+    }
+  }
+  /** Swaps foreground and background. Turns off auto background so the swap is visible. */
+  pub fn swap_palettes(&mut self) {
+    std::mem::swap(&mut self.foreground, &mut self.background);
+    self.auto_background = false;
+  }
+  /** Cells added when the world grows get an empty tile in the selected palettes. */
+  pub fn fill_key(&self) -> SpriteKey {
+    SpriteKey::Specific(SpritePaletteKey::new(
+      SpriteId::EMPTY,
+      self.foreground,
+      self.background,
+    ))
+  }
+  /** Without a selected sprite a click only recolours the tile. */
+  pub fn has_sprite(&self) -> bool {
+    self.sid.is_some() || self.group.is_some() || self.nine.is_some()
+  }
+  /** Label for the history entry of an edit made with these selections. */
+  pub fn action_label(&self) -> &'static str {
+    if self.has_sprite() {
+      "Place sprite"
+    } else {
+      "Recolour"
+    }
+  }
+
+  pub fn is_selected(&self, kind: &ButtonKind) -> bool {
+    match kind {
+      ButtonKind::Palette(pn) => pn == &self.foreground,
+      ButtonKind::BackgroundPalette(pn) => !self.auto_background && pn == &self.background,
+      ButtonKind::AutoBackground => self.auto_background,
+      ButtonKind::Sprite(sid) => self.sid.as_ref() == Some(sid),
+      ButtonKind::SpriteGroup(sg, index) => self
+        .group
+        .as_ref()
+        .is_some_and(|g| &g.group == sg && &g.index == index),
+      ButtonKind::SpriteNine(sg, index) => self
+        .nine
+        .as_ref()
+        .is_some_and(|n| &n.group == sg && &n.index == index),
+      //Layers belong to the world, see `ui::draw_ui`.
+      ButtonKind::Layer(_) | ButtonKind::LayerVisible(_) | ButtonKind::LayerLocked(_) => false,
+    }
+  }
+  /** Applies a clicked button. Clicking the selected sprite again goes back to recolouring. */
+  pub fn select(&mut self, kind: ButtonKind) {
+    match kind {
+      ButtonKind::Palette(pn) => {
+        self.foreground = pn;
+        return;
+      }
+      ButtonKind::BackgroundPalette(pn) => {
+        self.background = pn;
+        self.auto_background = false;
+        return;
+      }
+      ButtonKind::AutoBackground => {
+        self.auto_background = !self.auto_background;
+        return;
+      }
+      ButtonKind::Layer(_) | ButtonKind::LayerVisible(_) | ButtonKind::LayerLocked(_) => return,
+      _ => {}
+    }
+    let deselect = self.is_selected(&kind);
+    self.sid = None;
+    self.group = None;
+    self.nine = None;
+    if deselect {
+      return;
+    }
+    match kind {
+      ButtonKind::Sprite(sid) => self.sid = Some(sid),
+      ButtonKind::SpriteGroup(group, index) => self.group = Some(SelectionGroup { group, index }),
+      ButtonKind::SpriteNine(group, index) => self.nine = Some(SelectionNine { group, index }),
+      ButtonKind::Palette(_)
+      | ButtonKind::BackgroundPalette(_)
+      | ButtonKind::AutoBackground
+      | ButtonKind::Layer(_)
+      | ButtonKind::LayerVisible(_)
+      | ButtonKind::LayerLocked(_) => {}
+    }
+  }
+
+  /** Eyedropper, selects the sprite and palettes of `key`. The blob mask is resolved on placing. */
+  pub fn pick(&mut self, key: &SpriteKey) {
+    self.sid = None;
+    self.group = None;
+    self.nine = None;
+    match key {
+      SpriteKey::Specific(s) => self.sid = Some(s.sid),
+      SpriteKey::Group(g) => {
+        self.group = Some(SelectionGroup {
+          group: g.sg,
+          index: g.sg_index,
+        })
+      }
+      SpriteKey::Nine(n) => {
+        self.nine = Some(SelectionNine {
+          group: n.sg,
+          index: n.sg_index,
+        })
+      }
+    }
+    self.foreground = *key.get_fg();
+    self.background = *key.get_bg();
+    self.auto_background = false;
+  }
+
   pub fn get_sprite_key(&self, world: &World, tile_x: &usize, tile_y: &usize) -> SpriteKey {
     if let Some(nine) = &self.nine {
       let surrounding = world.get_surrounding(tile_x, tile_y);
 
       return SpriteKey::Nine(SpriteNinePaletteKey::new(
         self.foreground,
-        self
-          .background
-          .unwrap_or_else(|| get_surrounding_bg(&surrounding)),
+        self.background_at(world, tile_x, tile_y),
         nine.group,
         nine.index,
-        NineSliceDir::get_dir_for_nine_tile(&surrounding, &nine.group),
+        BlobMask::from_surrounding(&surrounding, &nine.group),
       ));
     }
 
-    let bg = self
-      .background
-      .unwrap_or_else(|| get_surrounding_bg(&world.get_surrounding(tile_x, tile_y)));
+    let bg = self.background_at(world, tile_x, tile_y);
     if let Some(sid) = &self.sid {
       return SpriteKey::Specific(SpritePaletteKey::new(*sid, self.foreground, bg));
     }
@@ -85,40 +217,55 @@ impl Selections {
       ));
     }
 
-    SpriteKey::Specific(SpritePaletteKey::new(SpriteId::Empty, self.foreground, bg))
+    SpriteKey::Specific(SpritePaletteKey::new(SpriteId::EMPTY, self.foreground, bg))
   }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Hit {
-  Tile((usize, usize)),
+  //Cells edited as one, e.g. everything under the brush, see `tools::Tools::update`.
+  Tiles(Vec<(usize, usize)>),
+  //Cells set to the given keys, e.g. a paste, see `selection::Selection::update`.
+  Keys(Vec<((usize, usize), Option<SpriteKey>)>),
+  //Eyedropper on a cell.
+  Pick(usize, usize),
 }
 
-pub fn check_hit(world: &mut World) -> Option<Hit> {
-  if !is_mouse_button_pressed(MouseButton::Left)
-    || world.mouse_world_px.is_none()
-    || world.mouse_grid.is_none()
-  {
-    return None;
-  }
-
-  if let Some((x, y)) = world.mouse_grid {
-    if let Some(_) = world.get_tile(&x, &y) {
-      return Some(Hit::Tile((x, y)));
-    }
-  }
-
-  None
-}
-
-pub fn handle_hit(hit: Hit, selected_palette: &PaletteName, world: &mut World) {
-  match hit {
-    Hit::Tile((x, y)) => {
-      if let Some(tile) = world.get_tile_mut(&x, &y) {
-        let mut s_key = tile.get_sprite_key().clone();
-        s_key.set_palettes(selected_palette.clone(), selected_palette.clone());
-        tile.set_visual(s_key);
+/**
+Edits go to the active layer, nothing happens while it is locked or hidden.
+Picking samples the tile that is seen, whatever layer it is on.
+*/
+pub fn handle_hit(hit: Hit, selections: &mut Selections, world: &mut World, history: &mut History) {
+  let cells = match hit {
+    Hit::Pick(x, y) => {
+      if let Some(tile) = world.top_tile(&x, &y) {
+        selections.pick(tile.get_sprite_key());
       }
+      return;
     }
-  }
+    _ if !world.is_active_layer_editable() => return,
+    Hit::Keys(keys) => {
+      history.set_tiles(world, keys);
+      return;
+    }
+    Hit::Tiles(cells) => cells,
+  };
+  //Keys are made before any cell changes, so the auto background of one cell does not
+  //depend on the cells painted before it.
+  let tiles = cells
+    .into_iter()
+    .filter_map(|(x, y)| {
+      if selections.has_sprite() {
+        Some(((x, y), Some(selections.get_sprite_key(world, &x, &y))))
+      } else {
+        let mut s_key = world.get_tile(&x, &y)?.get_sprite_key().clone();
+        s_key.set_palettes(
+          selections.foreground,
+          selections.background_at(world, &x, &y),
+        );
+        Some(((x, y), Some(s_key)))
+      }
+    })
+    .collect();
+  history.set_tiles(world, tiles);
 }
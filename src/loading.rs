@@ -1,6 +1,9 @@
+use std::{fs, path::Path};
+
 use enum_map::{enum_map, EnumMap};
 use hashbrown::HashMap;
 use macroquad::{
+  file::load_string,
   prelude::Rect,
   text::load_ttf_font,
   texture::{load_image, Image, Texture2D},
@@ -9,63 +12,131 @@ use strum::IntoEnumIterator;
 
 use crate::{
   assets::Sprites,
+  autotile::{BlobMask, BLOB_SHEET_COLUMNS, BLOB_SHEET_ROWS, BLOB_TILE_COUNT},
   debug::DebugState,
+  map_file,
   palette::{colorize_sprite, PaletteName},
-  sprite::{NineSliceDir, SpriteId, SpriteNineGroup},
+  registry::{registry, SpriteDef, SpriteRegistry, MANIFEST_NAME},
+  sprite::NineSliceDir,
+  stamps::Stamp,
   ui::{FontWeight, Fonts, Lang},
-  world::{World, TILE_SIZE},
+  world::{World, DEFAULT_LAYERS, TILE_SIZE},
 };
 
 static ASSET_PATH: &str = "assets";
 static TEXTURE_PATH: &str = "assets/textures";
+static STAMP_PATH: &str = "assets/stamps";
+const STAMP_EXTENSION: &str = "stamp";
 
 fn tex_path(name: &str) -> String {
   format!("{}/{}.png", TEXTURE_PATH, name)
 }
-fn world_path(name: &str) -> String {
+pub fn world_path(name: &str) -> String {
   format!("{}/{}.png", ASSET_PATH, name)
 }
+/** The ground layer is `<name>.png`, the layers above it `<name>_<layer>.png`. */
+pub fn layer_path(name: &str, layer: usize, layer_name: &str) -> String {
+  if layer == 0 {
+    world_path(name)
+  } else {
+    world_path(&format!("{}_{}", name, layer_name))
+  }
+}
+pub fn map_path(name: &str) -> String {
+  format!("{}/{}.map", ASSET_PATH, name)
+}
+pub fn stamp_path(name: &str) -> String {
+  format!("{}/{}.{}", STAMP_PATH, name, STAMP_EXTENSION)
+}
 fn font_path(name: Lang, weight: FontWeight) -> String {
   let folder = match name {
     Lang::ThTh => "ThTh",
     _ => "west",
   };
-  format!("{}/fonts/{}/{}.ttf", ASSET_PATH, folder, weight.to_string())
+  format!("{}/fonts/{}/{}.ttf", ASSET_PATH, folder, weight)
+}
+
+pub async fn load_registry() -> Result<SpriteRegistry, String> {
+  let path = format!("{}/{}", TEXTURE_PATH, MANIFEST_NAME);
+  let data = load_string(&path)
+    .await
+    .map_err(|e| format!("Could not read sprite manifest '{}': {}", path, e))?;
+  SpriteRegistry::parse(&data)
 }
 
-pub async fn load_sprites(deb: &DebugState) -> Sprites {
-  Sprites {
-    empty: load_image(&tex_path("Empty")).await.unwrap(),
+/** Loads the frames of every sprite in the registry, see `init_registry`. */
+pub async fn load_sprites(deb: &DebugState) -> Result<Sprites, String> {
+  let mut errors = vec![];
+  let mut sprites = vec![];
+  for def in registry().sprites() {
+    sprites.push(load_sprite_sequence(deb, def, &mut errors).await);
+  }
+  let mut group_sprites = vec![];
+  for def in registry().groups() {
+    group_sprites.push(load_sprite_sequence(deb, def, &mut errors).await);
+  }
+  let mut nine_slice_sprites = vec![];
+  for def in registry().nines() {
+    let sheets = load_sprite_sequence(deb, def, &mut errors).await;
+    nine_slice_sprites.push(slice_nine_sheets(&sheets));
+  }
+  if !errors.is_empty() {
+    return Err(format!("Missing sprite frames:\n  {}", errors.join("\n  ")));
+  }
+
+  Ok(Sprites {
     empty_full: load_image(&tex_path("empty_full")).await.unwrap(),
     palette: load_image(&tex_path("BtnPalette")).await.unwrap(),
     palettes: load_palettes().await,
-    sprites: enum_map! {
-      SpriteId::Empty => load_sprite_sequence(deb, "Empty").await,
-      SpriteId::Missing => load_sprite_sequence(deb, "Missing").await,
-    },
-    nine_slice_sprites: load_nine_slices(deb).await,
-  }
+    sprites,
+    group_sprites,
+    nine_slice_sprites,
+  })
 }
-async fn load_sprite_sequence(deb: &DebugState, name: &str) -> Vec<Image> {
-  let mut imgs = vec![];
-  if let Ok(img) = load_image(&tex_path(name)).await {
-    imgs.push(img);
-  } else if let Ok(img) = load_image(&tex_path(format!("{}_1", name).as_str())).await {
-    imgs.push(img);
+/** Reloads the frames of a single sprite, see `hot_reload`. */
+pub async fn load_sprite_frames(deb: &DebugState, def: &SpriteDef) -> Result<Vec<Image>, String> {
+  let mut errors = vec![];
+  let frames = load_sprite_sequence(deb, def, &mut errors).await;
+  if errors.is_empty() {
+    Ok(frames)
   } else {
-    if deb.allow_missing_sprites {
-      imgs.push(load_image(&tex_path("missing")).await.unwrap());
-      return imgs;
-    }
-    panic!(
-      "Could not find sprite \'{}\' or fallback \'{}\'.",
-      tex_path(format!("{}_1", name).as_str()),
-      tex_path(name)
-    );
+    Err(errors.join(" "))
+  }
+}
+/** Every file `load_sprite_sequence` may read for a sprite. */
+pub fn sprite_frame_paths(def: &SpriteDef) -> Vec<String> {
+  let mut paths: Vec<String> = (1..=def.frames)
+    .map(|frame| tex_path(format!("{}_{}", def.file, frame).as_str()))
+    .collect();
+  if def.frames == 1 {
+    paths.push(tex_path(&def.file));
   }
-  for letter in ['2', '3', '4', '5', '6', '7', '8'] {
-    if let Ok(img) = load_image(&tex_path(format!("{}_{}", name, letter).as_str())).await {
-      imgs.push(img);
+  paths
+}
+/**
+Loads `def.frames` frames. Missing frames are added to `errors`, or replaced by the missing
+sprite when the debug state allows it.
+*/
+async fn load_sprite_sequence(
+  deb: &DebugState,
+  def: &SpriteDef,
+  errors: &mut Vec<String>,
+) -> Vec<Image> {
+  let mut imgs = vec![];
+  for frame in 1..=def.frames {
+    let numbered = tex_path(format!("{}_{}", def.file, frame).as_str());
+    let img = match load_image(&numbered).await {
+      Ok(img) => Ok(img),
+      Err(_) if def.frames == 1 => load_image(&tex_path(&def.file)).await,
+      Err(e) => Err(e),
+    };
+    match img {
+      Ok(img) => imgs.push(img),
+      Err(_) if deb.allow_missing_sprites => {
+        println!("Sprite '{}' is missing frame {}.", def.name, numbered);
+        imgs.push(load_image(&tex_path("missing")).await.unwrap());
+      }
+      Err(_) => errors.push(format!("'{}' is missing frame {}.", def.name, numbered)),
     }
   }
   imgs
@@ -78,7 +149,7 @@ async fn load_palettes() -> HashMap<PaletteName, (Texture2D, Texture2D, Texture2
   let mut palettes = HashMap::new();
   for pn in PaletteName::iter() {
     palettes.insert(
-      pn.clone(),
+      pn,
       (
         Texture2D::from_image(&colorize_sprite(&palette, &pn, None)),
         Texture2D::from_image(&colorize_sprite(&palette_hover, &pn, None)),
@@ -90,48 +161,138 @@ async fn load_palettes() -> HashMap<PaletteName, (Texture2D, Texture2D, Texture2
   palettes
 }
 
-async fn load_nine_slices(
-  deb: &DebugState,
-) -> EnumMap<SpriteNineGroup, HashMap<u8, EnumMap<NineSliceDir, Image>>> {
-  enum_map! {
-    SpriteNineGroup::Trees => load_nine_slice(deb, &SpriteNineGroup::Trees).await,
-    SpriteNineGroup::Mounds => load_nine_slice(deb, &SpriteNineGroup::Mounds).await,
-  }
+/**
+Slices every sheet of a nine group into `BLOB_TILE_COUNT` tiles indexed by `BlobMask::index`.
+Extended sheets are `BLOB_SHEET_COLUMNS` x `BLOB_SHEET_ROWS` tiles in that order, classic 3x3
+sheets fill the blob tiles with their closest piece.
+*/
+pub fn slice_nine_sheets(sheets: &[Image]) -> HashMap<u8, Vec<Image>> {
+  let mut slices = HashMap::new();
+  sheets.iter().enumerate().for_each(|(sg_index, sg_sprite)| {
+    slices.insert(sg_index as u8, slice_blob_sheet(sg_sprite));
+  });
+  slices
 }
 
-async fn load_nine_slice(
-  deb: &DebugState,
-  sg: &SpriteNineGroup,
-) -> HashMap<u8, EnumMap<NineSliceDir, Image>> {
+fn slice_blob_sheet(sheet: &Image) -> Vec<Image> {
   let size = TILE_SIZE as f32;
-  let sprites = load_sprite_sequence(deb, &sg.to_string()).await;
-  let mut slices = HashMap::new();
-  sprites
-    .iter()
-    .enumerate()
-    .for_each(|(sg_index, sg_sprite)| {
-      slices.insert(
-        sg_index as u8,
-        enum_map! {
-          NineSliceDir::LeftUp => sg_sprite.sub_image(Rect { w: size, h: size, x: 0.0, y: 0.0}),
-          NineSliceDir::Up => sg_sprite.sub_image(Rect { w: size, h: size, x: size, y: 0.0}),
-          NineSliceDir::RightUp => sg_sprite.sub_image(Rect { w: size, h: size, x: size * 2.0, y: 0.0}),
-
-          NineSliceDir::Center => sg_sprite.sub_image(Rect { w: size, h: size, x: 0.0, y: size}),
-          NineSliceDir::Left => sg_sprite.sub_image(Rect { w: size, h: size, x: size, y: size}),
-          NineSliceDir::Right => sg_sprite.sub_image(Rect { w: size, h: size, x: size * 2.0, y: size}),
-
-          NineSliceDir::RightDown => sg_sprite.sub_image(Rect { w: size, h: size, x: 0.0, y: size * 2.0}),
-          NineSliceDir::Down => sg_sprite.sub_image(Rect { w: size, h: size, x: size, y: size * 2.0}),
-          NineSliceDir::LeftDown => sg_sprite.sub_image(Rect { w: size, h: size, x: size * 2.0, y: size * 2.0}),
-        },
-      );
-    });
-  slices
+  let tile_at = |col: usize, row: usize| {
+    sheet.sub_image(Rect {
+      w: size,
+      h: size,
+      x: col as f32 * size,
+      y: row as f32 * size,
+    })
+  };
+
+  if sheet.width() >= BLOB_SHEET_COLUMNS * TILE_SIZE
+    && sheet.height() >= BLOB_SHEET_ROWS * TILE_SIZE
+  {
+    return (0..BLOB_TILE_COUNT)
+      .map(|i| tile_at(i % BLOB_SHEET_COLUMNS, i / BLOB_SHEET_COLUMNS))
+      .collect();
+  }
+
+  let nine: EnumMap<NineSliceDir, Image> = enum_map! {
+    NineSliceDir::LeftUp => tile_at(0, 0),
+    NineSliceDir::Up => tile_at(1, 0),
+    NineSliceDir::RightUp => tile_at(2, 0),
+
+    NineSliceDir::Left => tile_at(0, 1),
+    NineSliceDir::Center => tile_at(1, 1),
+    NineSliceDir::Right => tile_at(2, 1),
+
+    NineSliceDir::LeftDown => tile_at(0, 2),
+    NineSliceDir::Down => tile_at(1, 2),
+    NineSliceDir::RightDown => tile_at(2, 2),
+  };
+  BlobMask::all()
+    .map(|mask| nine[mask.to_nine_slice_dir()].clone())
+    .collect()
 }
 
 pub async fn load_world() -> World {
-  World::new(load_image(&world_path("world")).await.unwrap())
+  try_load_world("world")
+    .await
+    .unwrap_or_else(|e| panic!("{}", e))
+}
+/** Reads the ground layer and any of the other `DEFAULT_LAYERS` that have a png, see `layer_path`. */
+pub async fn try_load_world(name: &str) -> Result<World, String> {
+  let path = world_path(name);
+  let mut world = load_image(&path)
+    .await
+    .map(World::new)
+    .map_err(|e| format!("Could not read world '{}': {}", path, e))?;
+  for (i, layer_name) in DEFAULT_LAYERS.iter().enumerate().skip(1) {
+    let path = layer_path(name, i, layer_name);
+    if !Path::new(&path).exists() {
+      continue;
+    }
+    load_image(&path)
+      .await
+      .map_err(|e| e.to_string())
+      .and_then(|img| world.load_layer_image(i, &img))
+      .map_err(|e| format!("Could not read layer '{}': {}", path, e))?;
+  }
+  Ok(world)
+}
+
+/**
+Writes every layer as a colour coded png that `load_world` can read back. Returns the paths
+written, bottom layer first.
+*/
+pub fn export_world(world: &World, name: &str) -> Result<Vec<String>, String> {
+  let mut paths = vec![];
+  for (i, layer) in world.layers().iter().enumerate() {
+    let img = world.to_image(i);
+    let path = layer_path(name, i, &layer.name);
+    //Not `Image::export_png`, it panics when the file cannot be written.
+    image::save_buffer(
+      &path,
+      &img.bytes,
+      img.width() as u32,
+      img.height() as u32,
+      image::ColorType::Rgba8,
+    )
+    .map_err(|e| format!("Could not export layer '{}': {}", path, e))?;
+    paths.push(path);
+  }
+  Ok(paths)
+}
+
+pub async fn load_map(name: &str) -> Result<World, String> {
+  let path = map_path(name);
+  let data = load_string(&path)
+    .await
+    .map_err(|e| format!("Could not read map file '{}': {}", path, e))?;
+  map_file::deserialize_world(&data)
+}
+
+/** Every stamp in the stamp folder by name. Broken stamp files are reported and skipped. */
+pub fn load_stamps() -> Vec<Stamp> {
+  let Ok(entries) = fs::read_dir(STAMP_PATH) else {
+    return vec![];
+  };
+  let mut stamps: Vec<Stamp> = entries
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| path.extension().is_some_and(|e| e == STAMP_EXTENSION))
+    .filter_map(|path| {
+      let name = path.file_stem()?.to_string_lossy().into_owned();
+      let block = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| map_file::deserialize_stamp(&data));
+      match block {
+        Ok(block) => Some(Stamp { name, block }),
+        Err(e) => {
+          println!("Could not load stamp '{}': {}", path.display(), e);
+          None
+        }
+      }
+    })
+    .collect();
+  stamps.sort_by(|a, b| a.name.cmp(&b.name));
+  stamps
 }
 
 pub async fn load_fonts() -> Fonts {
@@ -153,3 +314,25 @@ pub async fn load_fonts() -> Fonts {
     }
   })
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    registry::init_test_registry,
+    sprite::{SpriteId, SpriteKey, SpritePaletteKey},
+  };
+
+  #[test]
+  fn export_reports_unwritable_paths() {
+    init_test_registry();
+    let key = SpriteKey::Specific(SpritePaletteKey::new(
+      SpriteId::EMPTY,
+      PaletteName::White,
+      PaletteName::Green,
+    ));
+    let world = World::blank(2, 2, &key);
+    let error = export_world(&world, "no_such_folder/world").unwrap_err();
+    assert!(error.contains("no_such_folder"), "{}", error);
+  }
+}
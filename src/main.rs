@@ -1,22 +1,49 @@
 mod assets;
+mod atlas;
+mod autotile;
 mod button;
+mod camera;
 mod debug;
+mod discard;
+mod history;
+mod hot_reload;
+mod input;
 mod interaction;
 mod loading;
+mod map_file;
 mod math;
+mod minimap;
+mod new_map;
 mod palette;
+mod picker;
 mod rect;
+mod registry;
+mod selection;
 mod sprite;
+mod stamps;
 mod tile;
+mod tools;
 mod ui;
 mod world;
 
 use button::ButtonKind;
 use debug::DebugState;
-use interaction::{check_hit, handle_hit};
+use discard::{DiscardPrompt, WorldReplacement};
+use history::{History, DEFAULT_HISTORY_DEPTH};
+use hot_reload::{AssetWatcher, ChangedAsset};
+use input::{is_ctrl_down, is_shift_down};
+use interaction::{handle_hit, Selections};
 use macroquad::prelude::*;
+use minimap::Minimap;
+use new_map::NewMapDialog;
 use palette::PaletteName;
+use stamps::{SaveStampDialog, Stamp, StampPanel};
+use tools::Tools;
 use ui::{create_ui_layout, draw_ui, UIHit};
+use world::Anchor;
+
+const MAP_NAME: &str = "world";
+const EXPORT_NAME: &str = "world_export";
 
 fn window_conf() -> Conf {
   Conf {
@@ -29,16 +56,26 @@ fn window_conf() -> Conf {
 
 #[macroquad::main(window_conf)]
 async fn main() {
+  //Random group variants, e.g. of placed stamps, differ between runs.
+  rand::srand(miniquad::date::now() as u64);
   let mut debug = DebugState {
     enabled: true,
     ..Default::default()
   };
   let mut assets = assets::Assets::new(&debug).await;
-  let tx = assets.get_empty_tex();
   let mut wrld = loading::load_world().await;
-  let mut palette = PaletteName::White;
-  // let mut neighbours = Neighbours::new();
-  let ui = create_ui_layout(&assets);
+  let mut history = History::new(DEFAULT_HISTORY_DEPTH);
+  let mut selections = Selections::new(PaletteName::White, PaletteName::Green);
+  let mut ui = create_ui_layout(&assets, &wrld);
+  let mut watcher = AssetWatcher::new(MAP_NAME);
+  let mut minimap = Minimap::default();
+  let mut new_map = NewMapDialog::default();
+  let mut save_stamp = SaveStampDialog::default();
+  let mut stamps = StampPanel::new(loading::load_stamps());
+  let mut discard_prompt = DiscardPrompt::default();
+  //Made by the new map dialog, waits for the prompt when there are unsaved edits.
+  let mut new_world = None;
+  let mut tools = Tools::default();
 
   loop {
     clear_background(Color::from_rgba(200, 200, 255, 255));
@@ -46,35 +83,255 @@ async fn main() {
 
     debug::update_debug(&mut debug);
 
-    for _ in 0..10000 {
-      draw_texture_ex(
-        tx,
-        10.0,
-        10.0,
-        WHITE,
-        DrawTextureParams {
-          dest_size: Some(vec2(16.0, 16.0)),
-          ..Default::default()
-        },
-      );
+    let mut reloaded = vec![];
+    //The world and its history are only replaced once the edits are saved or given up.
+    let mut replace_world = None;
+    let changed = watcher.poll();
+    if !changed.is_empty() {
+      reloaded = assets.reload(&debug, &changed).await;
+      if changed.contains(&ChangedAsset::World) {
+        if history.has_unsaved_edits() {
+          discard_prompt.show(WorldReplacement::Reload);
+        } else {
+          replace_world = Some(WorldReplacement::Reload);
+        }
+      }
     }
 
-    wrld.update();
+    //Keys go to the dialog fields while it is open.
+    let keys_captured = new_map.open || save_stamp.open || discard_prompt.is_open();
+    if !is_ctrl_down() && !keys_captured {
+      if is_key_pressed(KeyCode::X) {
+        selections.swap_palettes();
+      }
+      tools.update_keys();
+    }
+
+    if is_ctrl_down() && !keys_captured {
+      if is_key_pressed(KeyCode::N) {
+        new_map.show(&wrld, selections.background());
+      }
+      if is_key_pressed(KeyCode::S) && is_shift_down() {
+        if tools.selection.selected_block(&wrld).is_some() {
+          save_stamp.show();
+        } else {
+          debug.show_toast("Select the tiles to save as a stamp first".to_owned());
+        }
+      } else if is_key_pressed(KeyCode::S) {
+        match map_file::save_map(&wrld, &loading::map_path(MAP_NAME)) {
+          Ok(()) => {
+            history.mark_saved();
+            println!("Saved map to {}.", loading::map_path(MAP_NAME));
+          }
+          Err(e) => println!("{}", e),
+        }
+      }
+      if is_key_pressed(KeyCode::E) {
+        match loading::export_world(&wrld, EXPORT_NAME) {
+          Ok(paths) => debug.show_toast(format!(
+            "Exported {} layers to {}",
+            paths.len(),
+            paths.join(", ")
+          )),
+          Err(e) => debug.show_toast(e),
+        }
+      }
+      if is_key_pressed(KeyCode::O) {
+        if history.has_unsaved_edits() {
+          discard_prompt.show(WorldReplacement::Open);
+        } else {
+          replace_world = Some(WorldReplacement::Open);
+        }
+      }
+      if is_key_pressed(KeyCode::Key0) {
+        wrld.fit_to_screen();
+      }
+      if is_key_pressed(KeyCode::P) {
+        wrld.camera.toggle_pixel_perfect_zoom();
+      }
+      if is_key_pressed(KeyCode::M) {
+        minimap.visible = !minimap.visible;
+      }
+      if is_key_pressed(KeyCode::G) {
+        wrld.camera.edge_panning = !wrld.camera.edge_panning;
+      }
+      //Arrows add a row or column on that side of the map, with shift they remove one.
+      //Hold two arrows to resize at a corner.
+      let arrows = [KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down];
+      if arrows.iter().any(|k| is_key_pressed(*k)) {
+        let side = |negative: KeyCode, positive: KeyCode| {
+          is_key_down(positive) as isize - is_key_down(negative) as isize
+        };
+        let (dx, dy) = (
+          side(KeyCode::Left, KeyCode::Right),
+          side(KeyCode::Up, KeyCode::Down),
+        );
+        let step = if is_shift_down() { -1 } else { 1 };
+        let width = (wrld.width as isize + dx.abs() * step).max(1) as usize;
+        let height = (wrld.height as isize + dy.abs() * step).max(1) as usize;
+        history.resize(
+          &mut wrld,
+          width,
+          height,
+          Anchor::opposite(dx, dy),
+          Some(selections.fill_key()),
+        );
+        //The world keeps to its size limits.
+        debug.show_toast(format!("Resized map to {}x{}", wrld.width, wrld.height));
+      }
+      if is_key_pressed(KeyCode::C) {
+        if let Some((width, height)) = tools.selection.copy(&wrld) {
+          debug.show_toast(format!("Copied {}x{} tiles", width, height));
+        }
+      }
+      if is_key_pressed(KeyCode::X) {
+        if let Some(hit) = tools.selection.cut(&wrld) {
+          history.begin_stroke("Cut");
+          handle_hit(hit, &mut selections, &mut wrld, &mut history);
+          history.end_stroke();
+        }
+      }
+      if is_key_pressed(KeyCode::V) {
+        tools.selection.paste();
+      }
+      if is_key_pressed(KeyCode::Z) {
+        if is_shift_down() {
+          history.redo(&mut wrld);
+        } else {
+          history.undo(&mut wrld);
+        }
+      }
+    }
+
+    let mouse_over_ui = ui.contains(&mouse_position())
+      || minimap.contains(&wrld, &mouse_position())
+      || new_map.contains(&mouse_position())
+      || save_stamp.contains(&mouse_position())
+      || stamps.contains(ui.stamp_area(), &mouse_position())
+      || discard_prompt.contains(&mouse_position())
+      || tools.contains(&mouse_position());
+    wrld.update(mouse_over_ui, keys_captured);
+    let minimap_used = minimap.update(&mut wrld);
+    if is_mouse_button_pressed(MouseButton::Middle) {
+      if let Some((x, y)) = wrld.mouse_grid {
+        wrld.center_on_tile(&x, &y);
+      }
+    }
 
     wrld.draw(&debug, &mut assets);
+    tools.selection.draw_floating(&wrld, &mut assets);
+    tools.draw_preview(
+      &wrld,
+      mouse_over_ui
+        || new_map.open
+        || save_stamp.open
+        || discard_prompt.is_open()
+        || tools.is_confirming(),
+    );
+    minimap.draw(&wrld);
 
-    if let Some(hit) = draw_ui(&debug, &assets, &ui, &palette) {
+    let stamp_hit = stamps.draw(&mut assets, ui.stamp_area());
+    let ui_hit = draw_ui(&debug, &mut assets, &mut ui, &selections, &wrld)
+      .or(stamp_hit)
+      .or(minimap_used.then_some(UIHit::Panel));
+    if let Some(hit) = ui_hit {
       match hit {
-        UIHit::Button(ButtonKind::Palette(pn)) => palette = pn,
-        _ => {}
+        UIHit::Button(ButtonKind::Layer(i)) => wrld.set_active_layer(i),
+        UIHit::Button(ButtonKind::LayerVisible(i)) => wrld.toggle_layer_visible(i),
+        UIHit::Button(ButtonKind::LayerLocked(i)) => wrld.toggle_layer_locked(i),
+        UIHit::Button(kind) => selections.select(kind),
+        UIHit::Stamp(i) => {
+          if let Some(stamp) = stamps.stamp(i) {
+            tools
+              .selection
+              .float(stamp.block.with_random_variants(&assets), "Stamp");
+          }
+        }
+        UIHit::Panel => {}
       }
+    } else if new_map.open || save_stamp.open || discard_prompt.is_open() || tools.is_confirming() {
+      //Modal, the world waits until the dialog is closed.
     } else {
-      if let Some(hit) = check_hit(&mut wrld) {
-        handle_hit(hit, &palette, &mut wrld);
+      //Placing a pasted block or stamp drops it, so its label is read before the update.
+      let label = tools.action_label(&selections);
+      if let Some(hit) = tools.update(&wrld, mouse_over_ui) {
+        //Brush strokes keep adding to the entry until the button is released.
+        if !history.in_stroke() {
+          history.begin_stroke(label);
+        }
+        handle_hit(hit, &mut selections, &mut wrld, &mut history);
+      }
+    }
+    if is_mouse_button_released(MouseButton::Left) {
+      history.end_stroke();
+    }
+
+    if let Some(hit) = tools.draw_fill_confirmation() {
+      history.begin_stroke(tools.action_label(&selections));
+      handle_hit(hit, &mut selections, &mut wrld, &mut history);
+      history.end_stroke();
+    }
+    if let Some(w) = new_map.draw() {
+      new_world = Some(w);
+      if history.has_unsaved_edits() {
+        discard_prompt.show(WorldReplacement::New);
+      } else {
+        replace_world = Some(WorldReplacement::New);
       }
     }
+    if let Some(name) = save_stamp.draw() {
+      if let Some(block) = tools.selection.selected_block(&wrld) {
+        match map_file::save_stamp(&block, &loading::stamp_path(&name)) {
+          Ok(()) => {
+            debug.show_toast(format!("Saved stamp {}", name));
+            stamps.add(Stamp { name, block });
+          }
+          Err(e) => println!("{}", e),
+        }
+      }
+    }
+
+    if let Some(replacement) = discard_prompt.draw() {
+      replace_world = Some(replacement);
+    }
+    match replace_world {
+      Some(WorldReplacement::Reload) => match loading::try_load_world(MAP_NAME).await {
+        Ok(mut w) => {
+          w.camera = wrld.camera;
+          w.set_active_layer(wrld.active_layer());
+          wrld = w;
+          ui = create_ui_layout(&assets, &wrld);
+          history.clear();
+          reloaded.push(loading::world_path(MAP_NAME));
+        }
+        Err(e) => println!("{}", e),
+      },
+      Some(WorldReplacement::Open) => match loading::load_map(MAP_NAME).await {
+        Ok(w) => {
+          wrld = w;
+          ui = create_ui_layout(&assets, &wrld);
+          history.clear();
+        }
+        Err(e) => println!("{}", e),
+      },
+      Some(WorldReplacement::New) => {
+        if let Some(w) = new_world.take() {
+          wrld = w;
+          wrld.fit_to_screen();
+          ui = create_ui_layout(&assets, &wrld);
+          history.clear();
+        }
+      }
+      None => {}
+    }
+    if !reloaded.is_empty() {
+      debug.show_toast(format!("Reloaded: {}", reloaded.join(", ")));
+    }
+
+    tools.draw_status(&assets);
+    debug.draw_texts(&assets, &wrld, &history);
 
-    debug.draw_texts(&assets, &wrld);
+    assets.flush_atlas();
 
     next_frame().await
   }
@@ -0,0 +1,613 @@
+use std::{fs, path::Path, str::FromStr, str::SplitWhitespace};
+
+use macroquad::prelude::vec2;
+
+use crate::{
+  autotile::BlobMask,
+  camera::Camera,
+  math::i_to_xy,
+  palette::PaletteName,
+  selection::TileBlock,
+  sprite::{
+    NineSliceDir, SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNineGroup,
+    SpriteNinePaletteKey, SpritePaletteKey,
+  },
+  tile::Tile,
+  world::{Layer, World, DEFAULT_LAYERS, MAX_MAP_SIZE},
+};
+
+/** Bump when the layout of the map file changes. */
+pub const MAP_FILE_VERSION: u32 = 4;
+//Version 1 stored a NineSliceDir name instead of the blob mask.
+//Versions before 3 stored the camera as a scroll offset, the negated camera position.
+//Versions before 4 had a single tile section, it becomes the ground layer.
+const OLDEST_MAP_FILE_VERSION: u32 = 1;
+const MAP_FILE_MAGIC: &str = "allrems-map";
+const STAMP_FILE_MAGIC: &str = "allrems-stamp";
+const EMPTY_CELL: &str = "-";
+const AUTO_INDEX: &str = "auto";
+const VISIBLE: &str = "visible";
+const HIDDEN: &str = "hidden";
+const LOCKED: &str = "locked";
+const UNLOCKED: &str = "unlocked";
+
+/*
+Map files are plain text so they can be diffed and fixed by hand:
+
+  allrems-map 4
+  size <width> <height>
+  camera <x> <y> <zoom>
+  layer <name> <visible or hidden> <locked or unlocked>
+  tiles
+  <one line per cell, row by row>
+  ...more layers, bottom to top
+
+Cell lines are `-` for an empty cell or one of
+  S <SpriteId> <foreground> <background>
+  G <SpriteGroup> <sg_index or auto> <foreground> <background>
+  N <SpriteNineGroup> <sg_index> <blob mask bits> <foreground> <background>
+
+Stamps use the same cells and share the version:
+
+  allrems-stamp 4
+  size <width> <height>
+  tiles
+  <one line per cell, row by row>
+*/
+
+pub fn save_map(world: &World, path: &str) -> Result<(), String> {
+  fs::write(path, serialize_world(world))
+    .map_err(|e| format!("Could not write map file '{}': {}", path, e))
+}
+
+pub fn serialize_world(world: &World) -> String {
+  let mut out = format!("{} {}\n", MAP_FILE_MAGIC, MAP_FILE_VERSION);
+  out.push_str(&format!("size {} {}\n", world.width, world.height));
+  out.push_str(&format!(
+    "camera {} {} {}\n",
+    world.camera.pos.x, world.camera.pos.y, world.camera.zoom
+  ));
+  for (i, layer) in world.layers().iter().enumerate() {
+    out.push_str(&format!(
+      "layer {} {} {}\n",
+      layer.name,
+      if layer.visible { VISIBLE } else { HIDDEN },
+      if layer.locked { LOCKED } else { UNLOCKED }
+    ));
+    out.push_str("tiles\n");
+    for y in 0..world.height {
+      for x in 0..world.width {
+        match world.get_layer_tile(i, &x, &y) {
+          Some(tile) => out.push_str(&serialize_sprite_key(tile.get_sprite_key())),
+          None => out.push_str(EMPTY_CELL),
+        }
+        out.push('\n');
+      }
+    }
+  }
+  out
+}
+
+pub fn save_stamp(block: &TileBlock, path: &str) -> Result<(), String> {
+  if let Some(dir) = Path::new(path).parent() {
+    fs::create_dir_all(dir)
+      .map_err(|e| format!("Could not create stamp folder '{}': {}", dir.display(), e))?;
+  }
+  fs::write(path, serialize_stamp(block))
+    .map_err(|e| format!("Could not write stamp file '{}': {}", path, e))
+}
+
+pub fn serialize_stamp(block: &TileBlock) -> String {
+  let mut out = format!("{} {}\n", STAMP_FILE_MAGIC, MAP_FILE_VERSION);
+  out.push_str(&format!("size {} {}\ntiles\n", block.width, block.height));
+  for y in 0..block.height {
+    for x in 0..block.width {
+      match block.get(x, y) {
+        Some(key) => out.push_str(&serialize_sprite_key(key)),
+        None => out.push_str(EMPTY_CELL),
+      }
+      out.push('\n');
+    }
+  }
+  out
+}
+
+pub fn serialize_sprite_key(key: &SpriteKey) -> String {
+  match key {
+    SpriteKey::Specific(s) => format!("S {} {} {}", s.sid, s.foreground, s.background),
+    SpriteKey::Group(g) => format!(
+      "G {} {} {} {}",
+      g.sg,
+      g.sg_index
+        .map(|i| i.to_string())
+        .unwrap_or_else(|| AUTO_INDEX.to_owned()),
+      g.foreground,
+      g.background
+    ),
+    SpriteKey::Nine(n) => format!(
+      "N {} {} {} {} {}",
+      n.sg,
+      n.sg_index,
+      n.blob.bits(),
+      n.foreground,
+      n.background
+    ),
+  }
+}
+
+pub fn deserialize_world(data: &str) -> Result<World, String> {
+  let mut lines = data
+    .lines()
+    .enumerate()
+    .map(|(i, l)| (i + 1, l.trim()))
+    .filter(|(_, l)| !l.is_empty());
+
+  let (line_nr, header) = lines.next().ok_or("Map file is empty.")?;
+  let mut tokens = header.split_whitespace();
+  if tokens.next() != Some(MAP_FILE_MAGIC) {
+    return Err("Not an allrems map file.".to_owned());
+  }
+  let version: u32 = parse_token(&mut tokens, line_nr, "version")?;
+  if !(OLDEST_MAP_FILE_VERSION..=MAP_FILE_VERSION).contains(&version) {
+    return Err(format!(
+      "Unsupported map file version {} (expected {} to {}).",
+      version, OLDEST_MAP_FILE_VERSION, MAP_FILE_VERSION
+    ));
+  }
+
+  let (line_nr, size) = lines.next().ok_or("Missing size.")?;
+  let (width, height) = parse_size(size, line_nr, "map")?;
+
+  let (line_nr, camera) = lines.next().ok_or("Missing camera.")?;
+  let mut tokens = expect_keyword(camera, line_nr, "camera")?;
+  let camera_x: f32 = parse_token(&mut tokens, line_nr, "camera x")?;
+  let camera_y: f32 = parse_token(&mut tokens, line_nr, "camera y")?;
+  let zoom: f32 = parse_token(&mut tokens, line_nr, "zoom")?;
+  //The camera divides by the zoom, anything else is clamped by `Camera::new`.
+  if !zoom.is_finite() || zoom <= 0.0 {
+    return Err(format!("Line {}: invalid zoom '{}'.", line_nr, zoom));
+  }
+  let camera_pos = if version < 3 {
+    -vec2(camera_x, camera_y)
+  } else {
+    vec2(camera_x, camera_y)
+  };
+
+  let mut layers = vec![];
+  if version < 4 {
+    let tiles = deserialize_tiles(&mut lines, width, height, version)?;
+    layers.push(Layer::new(DEFAULT_LAYERS[0], tiles));
+    layers.extend(
+      DEFAULT_LAYERS[1..]
+        .iter()
+        .map(|name| Layer::empty(name, width * height)),
+    );
+    if let Some((line_nr, _)) = lines.next() {
+      return Err(format!(
+        "Line {}: more tiles than {}x{}.",
+        line_nr, width, height
+      ));
+    }
+  }
+  while let Some((line_nr, line)) = lines.next() {
+    let mut tokens = expect_keyword(line, line_nr, "layer")?;
+    let name: String = parse_token(&mut tokens, line_nr, "layer name")?;
+    //Exports use the name in a file name, see `loading::layer_path`.
+    if !name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+      return Err(format!(
+        "Line {}: layer name '{}' may only use letters, digits, - and _.",
+        line_nr, name
+      ));
+    }
+    let visible = match tokens.next() {
+      Some(VISIBLE) => true,
+      Some(HIDDEN) => false,
+      _ => {
+        return Err(format!(
+          "Line {}: expected '{}' or '{}'.",
+          line_nr, VISIBLE, HIDDEN
+        ))
+      }
+    };
+    let locked = match tokens.next() {
+      Some(LOCKED) => true,
+      Some(UNLOCKED) => false,
+      _ => {
+        return Err(format!(
+          "Line {}: expected '{}' or '{}'.",
+          line_nr, LOCKED, UNLOCKED
+        ))
+      }
+    };
+    let mut layer = Layer::new(
+      &name,
+      deserialize_tiles(&mut lines, width, height, version)?,
+    );
+    layer.visible = visible;
+    layer.locked = locked;
+    layers.push(layer);
+  }
+  if layers.is_empty() {
+    return Err("Missing layers.".to_owned());
+  }
+
+  Ok(World::from_layers(
+    width,
+    height,
+    layers,
+    Camera::new(camera_pos, zoom),
+  ))
+}
+
+pub fn deserialize_stamp(data: &str) -> Result<TileBlock, String> {
+  let mut lines = data
+    .lines()
+    .enumerate()
+    .map(|(i, l)| (i + 1, l.trim()))
+    .filter(|(_, l)| !l.is_empty());
+
+  let (line_nr, header) = lines.next().ok_or("Stamp file is empty.")?;
+  let mut tokens = header.split_whitespace();
+  if tokens.next() != Some(STAMP_FILE_MAGIC) {
+    return Err("Not an allrems stamp file.".to_owned());
+  }
+  let version: u32 = parse_token(&mut tokens, line_nr, "version")?;
+  if !(OLDEST_MAP_FILE_VERSION..=MAP_FILE_VERSION).contains(&version) {
+    return Err(format!(
+      "Unsupported stamp file version {} (expected {} to {}).",
+      version, OLDEST_MAP_FILE_VERSION, MAP_FILE_VERSION
+    ));
+  }
+
+  let (line_nr, size) = lines.next().ok_or("Missing size.")?;
+  let (width, height) = parse_size(size, line_nr, "stamp")?;
+
+  let tiles = deserialize_tiles(&mut lines, width, height, version)?;
+  if let Some((line_nr, _)) = lines.next() {
+    return Err(format!(
+      "Line {}: more tiles than {}x{}.",
+      line_nr, width, height
+    ));
+  }
+  Ok(TileBlock::new(
+    width,
+    height,
+    tiles
+      .into_iter()
+      .map(|t| t.map(|t| t.get_sprite_key().clone()))
+      .collect(),
+  ))
+}
+
+/** Reads a `size` line. Both sides are 1 to `MAX_MAP_SIZE` so the cells fit in memory. */
+fn parse_size(line: &str, line_nr: usize, what: &str) -> Result<(usize, usize), String> {
+  let mut tokens = expect_keyword(line, line_nr, "size")?;
+  let width: usize = parse_token(&mut tokens, line_nr, "width")?;
+  let height: usize = parse_token(&mut tokens, line_nr, "height")?;
+  let valid = 1..=MAX_MAP_SIZE;
+  if !valid.contains(&width) || !valid.contains(&height) {
+    return Err(format!(
+      "Line {}: a {} must be 1 to {} tiles wide and high, not {}x{}.",
+      line_nr, what, MAX_MAP_SIZE, width, height
+    ));
+  }
+  Ok((width, height))
+}
+
+/** Reads a `tiles` line and the width x height cell lines after it. */
+fn deserialize_tiles<'a>(
+  lines: &mut impl Iterator<Item = (usize, &'a str)>,
+  width: usize,
+  height: usize,
+  version: u32,
+) -> Result<Vec<Option<Tile>>, String> {
+  let (line_nr, tiles_start) = lines.next().ok_or("Missing tiles.")?;
+  expect_keyword(tiles_start, line_nr, "tiles")?;
+
+  let mut tiles = Vec::with_capacity(width * height);
+  while tiles.len() < width * height {
+    let Some((line_nr, line)) = lines.next() else {
+      return Err(format!(
+        "Expected {} tiles but found {}.",
+        width * height,
+        tiles.len()
+      ));
+    };
+    let (x, y) = i_to_xy(&width, &tiles.len());
+    if line == EMPTY_CELL {
+      tiles.push(None);
+    } else {
+      tiles.push(Some(Tile::from_sprite_key(
+        x,
+        y,
+        deserialize_sprite_key(line, line_nr, version)?,
+      )));
+    }
+  }
+  Ok(tiles)
+}
+
+pub fn deserialize_sprite_key(
+  line: &str,
+  line_nr: usize,
+  version: u32,
+) -> Result<SpriteKey, String> {
+  let mut tokens = line.split_whitespace();
+  let key = match tokens.next() {
+    Some("S") => {
+      let sid: SpriteId = parse_token(&mut tokens, line_nr, "sprite id")?;
+      let foreground: PaletteName = parse_token(&mut tokens, line_nr, "foreground")?;
+      let background: PaletteName = parse_token(&mut tokens, line_nr, "background")?;
+      SpriteKey::Specific(SpritePaletteKey::new(sid, foreground, background))
+    }
+    Some("G") => {
+      let sg: SpriteGroup = parse_token(&mut tokens, line_nr, "sprite group")?;
+      let sg_index: Option<u8> = match parse_token::<String>(&mut tokens, line_nr, "group index")? {
+        i if i == AUTO_INDEX => None,
+        i => Some(
+          i.parse()
+            .map_err(|_| format!("Line {}: invalid group index '{}'.", line_nr, i))?,
+        ),
+      };
+      let foreground: PaletteName = parse_token(&mut tokens, line_nr, "foreground")?;
+      let background: PaletteName = parse_token(&mut tokens, line_nr, "background")?;
+      SpriteKey::Group(SpriteGroupPaletteKey::new(
+        foreground, background, sg, sg_index,
+      ))
+    }
+    Some("N") => {
+      let sg: SpriteNineGroup = parse_token(&mut tokens, line_nr, "nine group")?;
+      let sg_index: u8 = parse_token(&mut tokens, line_nr, "group index")?;
+      let blob = if version == 1 {
+        let dir: NineSliceDir = parse_token(&mut tokens, line_nr, "nine slice direction")?;
+        BlobMask::from_nine_slice_dir(dir)
+      } else {
+        BlobMask::from_bits(parse_token(&mut tokens, line_nr, "blob mask")?)
+      };
+      let foreground: PaletteName = parse_token(&mut tokens, line_nr, "foreground")?;
+      let background: PaletteName = parse_token(&mut tokens, line_nr, "background")?;
+      SpriteKey::Nine(SpriteNinePaletteKey::new(
+        foreground, background, sg, sg_index, blob,
+      ))
+    }
+    other => {
+      return Err(format!(
+        "Line {}: unknown tile kind {:?}.",
+        line_nr,
+        other.unwrap_or("")
+      ))
+    }
+  };
+  if tokens.next().is_some() {
+    return Err(format!("Line {}: trailing data after tile.", line_nr));
+  }
+  Ok(key)
+}
+
+fn expect_keyword<'a>(
+  line: &'a str,
+  line_nr: usize,
+  keyword: &str,
+) -> Result<SplitWhitespace<'a>, String> {
+  let mut tokens = line.split_whitespace();
+  if tokens.next() != Some(keyword) {
+    return Err(format!("Line {}: expected '{}'.", line_nr, keyword));
+  }
+  Ok(tokens)
+}
+
+fn parse_token<T: FromStr>(
+  tokens: &mut SplitWhitespace,
+  line_nr: usize,
+  what: &str,
+) -> Result<T, String> {
+  let token = tokens
+    .next()
+    .ok_or_else(|| format!("Line {}: missing {}.", line_nr, what))?;
+  token
+    .parse()
+    .map_err(|_| format!("Line {}: invalid {} '{}'.", line_nr, what, token))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    camera::{MAX_ZOOM, MIN_ZOOM},
+    registry::init_test_registry,
+  };
+
+  fn empty_key(foreground: PaletteName, background: PaletteName) -> SpriteKey {
+    SpriteKey::Specific(SpritePaletteKey::new(
+      SpriteId::EMPTY,
+      foreground,
+      background,
+    ))
+  }
+
+  fn layer_key(world: &World, layer: usize, x: usize, y: usize) -> Option<SpriteKey> {
+    world
+      .get_layer_tile(layer, &x, &y)
+      .map(|t| t.get_sprite_key().clone())
+  }
+
+  #[test]
+  fn round_trips_a_multi_layer_world() {
+    init_test_registry();
+    let mut world = World::blank(3, 2, &empty_key(PaletteName::White, PaletteName::Green));
+    let tree = SpriteKey::Group(SpriteGroupPaletteKey::new(
+      PaletteName::Brown,
+      PaletteName::Green,
+      "Tree".parse().unwrap(),
+      None,
+    ));
+    let trees = SpriteKey::Nine(SpriteNinePaletteKey::new(
+      PaletteName::Green,
+      PaletteName::Blue,
+      "Trees".parse().unwrap(),
+      0,
+      BlobMask::ISOLATED,
+    ));
+    world.set_sprite_key(1, &2, &0, Some(tree.clone()));
+    world.set_sprite_key(2, &1, &1, Some(trees.clone()));
+    world.toggle_layer_visible(1);
+    world.toggle_layer_locked(2);
+
+    let data = serialize_world(&world);
+    let loaded = deserialize_world(&data).unwrap();
+    assert_eq!(serialize_world(&loaded), data);
+    assert_eq!((loaded.width, loaded.height), (3, 2));
+    assert_eq!(loaded.layers().len(), 3);
+    assert!(!loaded.layers()[1].visible);
+    assert!(loaded.layers()[2].locked);
+    assert_eq!(layer_key(&loaded, 1, 2, 0), Some(tree));
+    assert_eq!(layer_key(&loaded, 2, 1, 1), Some(trees));
+    assert_eq!(layer_key(&loaded, 1, 0, 0), None);
+  }
+
+  #[test]
+  fn loads_version_1_nine_slice_directions() {
+    init_test_registry();
+    let data = "allrems-map 1\nsize 2 1\ncamera 16 32 2\ntiles\nN Trees 0 Center White Green\n-\n";
+    let world = deserialize_world(data).unwrap();
+    let Some(SpriteKey::Nine(nine)) = layer_key(&world, 0, 0, 0) else {
+      panic!("Expected a nine-slice tile.");
+    };
+    assert_eq!(
+      nine.blob,
+      BlobMask::from_nine_slice_dir(NineSliceDir::Center)
+    );
+    assert_eq!(layer_key(&world, 0, 1, 0), None);
+    assert_eq!(world.camera.pos, vec2(-16.0, -32.0));
+  }
+
+  #[test]
+  fn loads_version_2_scroll_offsets() {
+    init_test_registry();
+    let data = "allrems-map 2\nsize 1 1\ncamera 16 32 2\ntiles\nN Trees 0 255 White Green\n";
+    let world = deserialize_world(data).unwrap();
+    let Some(SpriteKey::Nine(nine)) = layer_key(&world, 0, 0, 0) else {
+      panic!("Expected a nine-slice tile.");
+    };
+    assert_eq!(nine.blob, BlobMask::from_bits(255));
+    assert_eq!(world.camera.pos, vec2(-16.0, -32.0));
+  }
+
+  #[test]
+  fn loads_version_3_as_ground_layer() {
+    init_test_registry();
+    let data = "allrems-map 3\nsize 2 1\ncamera 16 32 2\ntiles\nS Empty White Green\n-\n";
+    let world = deserialize_world(data).unwrap();
+    assert_eq!(world.camera.pos, vec2(16.0, 32.0));
+    let names: Vec<&str> = world.layers().iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, DEFAULT_LAYERS);
+    assert_eq!(
+      layer_key(&world, 0, 0, 0),
+      Some(empty_key(PaletteName::White, PaletteName::Green))
+    );
+    assert!((0..2).all(|x| layer_key(&world, 1, x, 0).is_none()));
+  }
+
+  #[test]
+  fn rejects_bad_input() {
+    init_test_registry();
+    let cases = [
+      ("", "empty"),
+      ("allrems-mop 4\nsize 1 1\ncamera 0 0 1\n", "wrong magic"),
+      ("allrems-map 5\nsize 1 1\ncamera 0 0 1\n", "future version"),
+      ("allrems-map 4\nsize 0 0\ncamera 0 0 1\n", "zero size"),
+      ("allrems-map 4\nsize 1 1\ncamera 0 0 1\n", "no layers"),
+      (
+        "allrems-map 4\nsize 2 1\ncamera 0 0 1\nlayer ground visible unlocked\ntiles\n-\n",
+        "short tile list",
+      ),
+      (
+        "allrems-map 3\nsize 1 1\ncamera 0 0 1\ntiles\n-\n-\n",
+        "extra tile in a single layer map",
+      ),
+      (
+        "allrems-map 4\nsize 1 1\ncamera 0 0 1\nlayer ground visible unlocked\ntiles\n-\n-\n",
+        "extra line after a layer",
+      ),
+      (
+        "allrems-map 4\nsize 1 1\ncamera 0 0 1\nlayer ground shown unlocked\ntiles\n-\n",
+        "bad visibility",
+      ),
+      (
+        "allrems-map 4\nsize 1 1\ncamera 0 0 1\nlayer ground visible unlocked\ntiles\nS Nope White Green\n",
+        "unknown sprite",
+      ),
+    ];
+    for (data, case) in cases {
+      assert!(deserialize_world(data).is_err(), "Accepted {}.", case);
+    }
+  }
+
+  #[test]
+  fn rejects_layer_names_that_are_not_file_names() {
+    init_test_registry();
+    let map = |name: &str| {
+      format!(
+        "allrems-map 4\nsize 1 1\ncamera 0 0 1\nlayer {} visible unlocked\ntiles\n-\n",
+        name
+      )
+    };
+    for name in ["../../x", "a/b", "a\\b", "x.png", "~"] {
+      assert!(deserialize_world(&map(name)).is_err(), "Accepted {}.", name);
+    }
+    let world = deserialize_world(&map("ground-2_b")).unwrap();
+    assert_eq!(world.layers()[0].name, "ground-2_b");
+  }
+
+  #[test]
+  fn rejects_sizes_out_of_range() {
+    init_test_registry();
+    let header = |width: usize, height: usize| {
+      format!(
+        "allrems-map 4\nsize {} {}\ncamera 0 0 1\nlayer ground visible unlocked\ntiles\n",
+        width, height
+      )
+    };
+    let Err(too_wide) = deserialize_world(&header(MAX_MAP_SIZE + 1, 1)) else {
+      panic!("Accepted a map wider than MAX_MAP_SIZE.");
+    };
+    assert!(too_wide.contains("must be 1 to 1024 tiles"), "{}", too_wide);
+    assert!(deserialize_world(&header(100000, 100000)).is_err());
+    assert!(deserialize_world(&header(1, 0)).is_err());
+    //Products that overflow must not be reached either.
+    assert!(deserialize_world(&header(usize::MAX, usize::MAX)).is_err());
+    let largest = header(MAX_MAP_SIZE, 1) + &"-\n".repeat(MAX_MAP_SIZE);
+    assert_eq!(deserialize_world(&largest).unwrap().width, MAX_MAP_SIZE);
+    assert!(deserialize_stamp("allrems-stamp 4\nsize 1025 1\ntiles\n").is_err());
+  }
+
+  #[test]
+  fn rejects_zooms_the_camera_cannot_use() {
+    init_test_registry();
+    let map = |zoom: &str| {
+      format!(
+        "allrems-map 4\nsize 1 1\ncamera 0 0 {}\nlayer ground visible unlocked\ntiles\n-\n",
+        zoom
+      )
+    };
+    for zoom in ["0", "-1", "NaN", "inf"] {
+      assert!(
+        deserialize_world(&map(zoom)).is_err(),
+        "Accepted zoom {}.",
+        zoom
+      );
+    }
+    assert_eq!(
+      deserialize_world(&map("100")).unwrap().camera.zoom,
+      MAX_ZOOM
+    );
+    assert_eq!(
+      deserialize_world(&map("0.001")).unwrap().camera.zoom,
+      MIN_ZOOM
+    );
+    assert_eq!(deserialize_world(&map("2")).unwrap().camera.zoom, 2.0);
+  }
+}
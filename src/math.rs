@@ -1,7 +1,5 @@
 use hashbrown::HashMap;
 
-use crate::tile::Tile;
-
 pub fn i_to_xy(width: &usize, index: &usize) -> (usize, usize) {
   (index % width, index / width)
 }
@@ -9,16 +7,13 @@ pub fn xy_to_i(width: &usize, x: &usize, y: &usize) -> usize {
   y * width + x
 }
 
-pub fn px_in_tile(world_px: &(usize, usize), tile: &Tile) -> (usize, usize) {
-  let rect = tile.get_drawing_rect();
-  if world_px.0 < rect.left
-    || world_px.0 > rect.right
-    || world_px.1 < rect.top
-    || world_px.1 > rect.bottom
-  {
-    panic!("Px not in tile. px{:?}    rect{:?}", world_px, rect);
-  }
-  (world_px.0 - rect.left, world_px.1 - rect.top)
+/** Cheap, stable hash of a grid position, used to scatter sprite variants. */
+pub fn position_hash(x: &usize, y: &usize) -> u32 {
+  let mut h = (*x as u32).wrapping_mul(0x9E37_79B1) ^ (*y as u32).wrapping_mul(0x85EB_CA77);
+  h ^= h >> 15;
+  h = h.wrapping_mul(0x2C1B_3C6D);
+  h ^= h >> 12;
+  h
 }
 
 // pub fn grid_pos_to_pos(grid_pos: &(usize, usize)) -> (usize, usize) {
@@ -31,6 +26,28 @@ pub fn px_in_tile(world_px: &(usize, usize), tile: &Tile) -> (usize, usize) {
 //   )
 // }
 
+/** Grid cells on the line between two cells, both ends included (Bresenham). */
+pub fn grid_line(from: (isize, isize), to: (isize, isize)) -> Vec<(isize, isize)> {
+  let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+  let (step_x, step_y) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+  let mut err = dx + dy;
+  let (mut x, mut y) = from;
+  let mut cells = vec![(x, y)];
+  while (x, y) != to {
+    let e2 = err * 2;
+    if e2 >= dy {
+      err += dy;
+      x += step_x;
+    }
+    if e2 <= dx {
+      err += dx;
+      y += step_y;
+    }
+    cells.push((x, y));
+  }
+  cells
+}
+
 pub fn mode(numbers: &[u8]) -> Option<u8> {
   let mut counts = HashMap::new();
 
@@ -0,0 +1,168 @@
+use macroquad::prelude::*;
+
+use crate::{
+  palette::{get_palette, PaletteName},
+  sprite::{SpriteId, SpriteKey},
+  tile::Tile,
+  world::{World, WorldChanges, TILE_SIZE},
+};
+
+/** Largest width or height of the minimap in screen pixels. */
+const MINIMAP_SIZE: f32 = 200.0;
+const MAX_PIXELS_PER_TILE: f32 = 4.0;
+const MINIMAP_MARGIN: f32 = 10.0;
+const MINIMAP_FRAME_COLOR: Color = Color::new(0.1, 0.1, 0.15, 0.8);
+const VIEWPORT_COLOR: Color = YELLOW;
+//Sprite pixel value looked up in the tile palette, 255 is the lightest.
+const TILE_HUE: u8 = 120;
+const EMPTY_PIXEL: [u8; 4] = [0, 0, 0, 0];
+
+/** Whole world at a pixel per tile in the bottom left corner. Click or drag to move the camera. */
+pub struct Minimap {
+  image: Image,
+  texture: Texture2D,
+  dragging: bool,
+  pub visible: bool,
+}
+
+impl Default for Minimap {
+  fn default() -> Minimap {
+    //Filled on the first update, see `World::take_changes`.
+    let image = Image::gen_image_color(1, 1, BLANK);
+    let texture = Texture2D::from_image(&image);
+    texture.set_filter(FilterMode::Nearest);
+    Minimap {
+      image,
+      texture,
+      dragging: false,
+      visible: true,
+    }
+  }
+}
+
+impl Minimap {
+  /** Screen area of the minimap for the current world size. */
+  pub fn area(&self, world: &World) -> Rect {
+    let scale = pixels_per_tile(world);
+    let (width, height) = (world.width as f32 * scale, world.height as f32 * scale);
+    Rect::new(
+      MINIMAP_MARGIN,
+      screen_height() - MINIMAP_MARGIN - height,
+      width,
+      height,
+    )
+  }
+
+  pub fn contains(&self, world: &World, pos: &(f32, f32)) -> bool {
+    self.visible && self.area(world).contains(vec2(pos.0, pos.1))
+  }
+
+  /**
+  Redraws changed tiles and moves the camera while the minimap is clicked or dragged.
+  Returns true when the mouse is used by the minimap.
+  */
+  pub fn update(&mut self, world: &mut World) -> bool {
+    self.sync(world);
+    if !self.visible {
+      self.dragging = false;
+      return false;
+    }
+
+    let area = self.area(world);
+    let mouse = mouse_position();
+    if is_mouse_button_pressed(MouseButton::Left) && area.contains(vec2(mouse.0, mouse.1)) {
+      self.dragging = true;
+    }
+    if !is_mouse_button_down(MouseButton::Left) {
+      self.dragging = false;
+    }
+    if self.dragging {
+      let scale = pixels_per_tile(world);
+      let tile_px = TILE_SIZE as f32 / scale;
+      let world_pos = (vec2(mouse.0, mouse.1) - area.point()) * tile_px;
+      world.camera.center_on(world_pos);
+      world.camera.clamp(world.map_size());
+    }
+    self.dragging
+  }
+
+  fn sync(&mut self, world: &mut World) {
+    match world.take_changes() {
+      WorldChanges::All => {
+        self.image = Image::gen_image_color(world.width as u16, world.height as u16, BLANK);
+        for y in 0..world.height {
+          for x in 0..world.width {
+            self.set_pixel(world, x, y);
+          }
+        }
+        self.texture = Texture2D::from_image(&self.image);
+        self.texture.set_filter(FilterMode::Nearest);
+      }
+      WorldChanges::Cells(cells) if !cells.is_empty() => {
+        for (x, y) in cells {
+          self.set_pixel(world, x, y);
+        }
+        self.texture.update(&self.image);
+      }
+      WorldChanges::Cells(_) => {}
+    }
+  }
+
+  fn set_pixel(&mut self, world: &World, x: usize, y: usize) {
+    let width = self.image.width();
+    self.image.get_image_data_mut()[y * width + x] = world
+      .top_tile(&x, &y)
+      .map(tile_color)
+      .unwrap_or(EMPTY_PIXEL);
+  }
+
+  pub fn draw(&self, world: &World) {
+    if !self.visible {
+      return;
+    }
+    let area = self.area(world);
+    draw_rectangle(
+      area.x - 2.0,
+      area.y - 2.0,
+      area.w + 4.0,
+      area.h + 4.0,
+      MINIMAP_FRAME_COLOR,
+    );
+    draw_texture_ex(
+      self.texture,
+      area.x,
+      area.y,
+      WHITE,
+      DrawTextureParams {
+        dest_size: Some(vec2(area.w, area.h)),
+        ..Default::default()
+      },
+    );
+
+    //Viewport, cut to the minimap since the camera may show some margin around the map.
+    let scale = pixels_per_tile(world) / TILE_SIZE as f32;
+    let top_left = world.camera.screen_to_world((0.0, 0.0)) * scale + area.point();
+    let size = world.camera.view_size() * scale;
+    let view = Rect::new(top_left.x, top_left.y, size.x, size.y);
+    if let Some(view) = view.intersect(area) {
+      draw_rectangle_lines(view.x, view.y, view.w, view.h, 1.0, VIEWPORT_COLOR);
+    }
+  }
+}
+
+fn pixels_per_tile(world: &World) -> f32 {
+  let largest = world.width.max(world.height).max(1) as f32;
+  (MINIMAP_SIZE / largest).min(MAX_PIXELS_PER_TILE)
+}
+
+/** Empty sprites show their background, everything else its foreground. */
+fn tile_color(tile: &Tile) -> [u8; 4] {
+  let key = tile.get_sprite_key();
+  let palette: &PaletteName = match key {
+    SpriteKey::Specific(s) if s.sid == SpriteId::EMPTY => &s.background,
+    SpriteKey::Specific(s) => &s.foreground,
+    SpriteKey::Group(g) => &g.foreground,
+    SpriteKey::Nine(n) => &n.foreground,
+  };
+  *get_palette(palette).get_hue(TILE_HUE)
+}
@@ -0,0 +1,177 @@
+use macroquad::{
+  hash,
+  prelude::{is_key_pressed, vec2, KeyCode, Vec2},
+  ui::{root_ui, widgets},
+  window::{screen_height, screen_width},
+};
+use strum::IntoEnumIterator;
+
+use crate::{
+  autotile::BlobMask,
+  button::ButtonKind,
+  palette::PaletteName,
+  sprite::{
+    SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNineGroup, SpriteNinePaletteKey,
+    SpritePaletteKey,
+  },
+  world::{World, MAX_MAP_SIZE},
+};
+
+const DIALOG_WIDTH: f32 = 340.0;
+const DIALOG_HEIGHT: f32 = 190.0;
+
+/** Asks for the size, sprite and background of a blank world. Modal while open. */
+pub struct NewMapDialog {
+  pub open: bool,
+  width: String,
+  height: String,
+  //Indexes into `fill_choices` and `PaletteName::iter`.
+  sprite: usize,
+  background: usize,
+  error: Option<String>,
+}
+
+impl Default for NewMapDialog {
+  fn default() -> NewMapDialog {
+    NewMapDialog {
+      open: false,
+      width: "64".to_owned(),
+      height: "64".to_owned(),
+      sprite: 0,
+      background: 0,
+      error: None,
+    }
+  }
+}
+
+impl NewMapDialog {
+  /** Opens the dialog with the size of the current world and the selected background. */
+  pub fn show(&mut self, world: &World, background: PaletteName) {
+    self.open = true;
+    self.width = world.width.to_string();
+    self.height = world.height.to_string();
+    self.background = PaletteName::iter()
+      .position(|pn| pn == background)
+      .unwrap_or(0);
+    self.error = None;
+  }
+
+  /** Draws the dialog while it is open. Returns the new world once it is created. */
+  pub fn draw(&mut self) -> Option<World> {
+    if !self.open {
+      return None;
+    }
+    let choices = fill_choices();
+    let sprite_names: Vec<String> = choices.iter().map(choice_name).collect();
+    let sprite_names: Vec<&str> = sprite_names.iter().map(|n| n.as_str()).collect();
+    let palette_names: Vec<String> = PaletteName::iter().map(|pn| pn.to_string()).collect();
+    let palette_names: Vec<&str> = palette_names.iter().map(|n| n.as_str()).collect();
+
+    let mut world = None;
+    let mut close = false;
+    let pos = vec2(
+      (screen_width() - DIALOG_WIDTH) / 2.0,
+      (screen_height() - DIALOG_HEIGHT) / 2.0,
+    );
+    widgets::Window::new(hash!(), pos, vec2(DIALOG_WIDTH, DIALOG_HEIGHT))
+      .label("New map")
+      .movable(false)
+      .ui(&mut root_ui(), |ui| {
+        widgets::InputText::new(hash!())
+          .label("Width")
+          .filter_numbers()
+          .ui(ui, &mut self.width);
+        widgets::InputText::new(hash!())
+          .label("Height")
+          .filter_numbers()
+          .ui(ui, &mut self.height);
+        widgets::ComboBox::new(hash!(), &sprite_names)
+          .label("Sprite")
+          .ui(ui, &mut self.sprite);
+        widgets::ComboBox::new(hash!(), &palette_names)
+          .label("Background")
+          .ui(ui, &mut self.background);
+        if let Some(error) = &self.error {
+          ui.label(None, error);
+        }
+        if ui.button(None, "Create") {
+          match self.create(&choices) {
+            Ok(w) => world = Some(w),
+            Err(e) => self.error = Some(e),
+          }
+        }
+        ui.same_line(0.0);
+        if ui.button(None, "Cancel") {
+          close = true;
+        }
+      });
+    if world.is_some() || close || is_key_pressed(KeyCode::Escape) {
+      self.open = false;
+    }
+    world
+  }
+
+  /** Whether the screen position is over the dialog. */
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    self.open && root_ui().is_mouse_over(Vec2::new(pos.0, pos.1))
+  }
+
+  fn create(&self, choices: &[ButtonKind]) -> Result<World, String> {
+    let size = |text: &str, what: &str| match text.parse::<usize>() {
+      Ok(size) if (1..=MAX_MAP_SIZE).contains(&size) => Ok(size),
+      _ => Err(format!("{} must be 1 to {}.", what, MAX_MAP_SIZE)),
+    };
+    let width = size(&self.width, "Width")?;
+    let height = size(&self.height, "Height")?;
+    let background = PaletteName::iter()
+      .nth(self.background)
+      .unwrap_or(PaletteName::Green);
+    let choice = choices.get(self.sprite).ok_or("Pick a sprite.")?;
+    Ok(World::blank(width, height, &fill_key(choice, background)))
+  }
+}
+
+/** Every sprite, then every group with automatic variants and the first sheet of every nine group. */
+fn fill_choices() -> Vec<ButtonKind> {
+  SpriteId::iter()
+    .map(ButtonKind::Sprite)
+    .chain(SpriteGroup::iter().map(|sg| ButtonKind::SpriteGroup(sg, None)))
+    .chain(SpriteNineGroup::iter().map(|sg| ButtonKind::SpriteNine(sg, 0)))
+    .collect()
+}
+
+fn choice_name(choice: &ButtonKind) -> String {
+  match choice {
+    ButtonKind::Sprite(sid) => sid.to_string(),
+    ButtonKind::SpriteGroup(sg, _) => format!("{} (group)", sg),
+    ButtonKind::SpriteNine(sg, _) => format!("{} (nine)", sg),
+    _ => String::new(),
+  }
+}
+
+/** The sprite in its own foreground on `background`. */
+fn fill_key(choice: &ButtonKind, background: PaletteName) -> SpriteKey {
+  match *choice {
+    ButtonKind::SpriteGroup(sg, sg_index) => SpriteKey::Group(SpriteGroupPaletteKey::new(
+      sg.def().foreground,
+      background,
+      sg,
+      sg_index,
+    )),
+    ButtonKind::SpriteNine(sg, sg_index) => SpriteKey::Nine(SpriteNinePaletteKey::new(
+      sg.def().foreground,
+      background,
+      sg,
+      sg_index,
+      BlobMask::ISOLATED,
+    )),
+    ButtonKind::Sprite(sid) => {
+      SpriteKey::Specific(SpritePaletteKey::new(sid, sid.def().foreground, background))
+    }
+    _ => SpriteKey::Specific(SpritePaletteKey::new(
+      SpriteId::EMPTY,
+      PaletteName::White,
+      background,
+    )),
+  }
+}
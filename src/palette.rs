@@ -1,8 +1,10 @@
 use enum_map::Enum;
 use macroquad::{prelude::Color, texture::Image};
-use strum::{Display, EnumIter, FromRepr};
+use strum::{Display, EnumIter, EnumString, FromRepr};
 
-#[derive(Clone, Copy, Debug, Enum, Hash, EnumIter, PartialEq, Eq, Display, FromRepr)]
+#[derive(
+  Clone, Copy, Debug, Enum, Hash, EnumIter, PartialEq, Eq, Display, EnumString, FromRepr,
+)]
 pub enum PaletteName {
   White = 0,
   Blue = 40,
@@ -0,0 +1,153 @@
+use macroquad::{
+  prelude::{mouse_position, mouse_wheel, Color},
+  shapes::draw_rectangle,
+};
+
+use crate::{
+  assets::Assets,
+  button::{Button, ButtonKind, ButtonLabel},
+  debug::DebugState,
+  interaction::Selections,
+  rect::Rect,
+  sprite::{SpriteGroup, SpriteId, SpriteNineGroup},
+};
+
+const THUMBNAIL_SIZE: usize = 32;
+const THUMBNAIL_MARGIN: usize = 4;
+const PICKER_COLUMNS: usize = 3;
+const PICKER_BACKGROUND: Color = Color::new(0.1, 0.1, 0.15, 0.6);
+
+/** Scrollable grid of sprite thumbnails. Scrolls a row at a time with the mouse wheel. */
+pub struct Picker {
+  area: Rect,
+  buttons: Vec<Button>,
+  first_row: usize,
+}
+
+impl Picker {
+  pub fn new(assets: &Assets, area: Rect) -> Picker {
+    let margin = Rect::new(
+      THUMBNAIL_MARGIN,
+      THUMBNAIL_MARGIN,
+      THUMBNAIL_MARGIN,
+      THUMBNAIL_MARGIN,
+    );
+    let buttons = picker_kinds(assets)
+      .into_iter()
+      .map(|kind| {
+        let label = match kind {
+          ButtonKind::SpriteGroup(_, None) => Some(ButtonLabel {
+            text: "auto".to_owned(),
+            color: None,
+            size: Some(12),
+            weight: None,
+          }),
+          _ => None,
+        };
+        Button::new(
+          &assets.fonts,
+          kind,
+          area.left,
+          area.top,
+          THUMBNAIL_SIZE,
+          THUMBNAIL_SIZE,
+          Some(margin),
+          None,
+          label,
+        )
+      })
+      .collect();
+
+    Picker {
+      area,
+      buttons,
+      first_row: 0,
+    }
+  }
+
+  pub fn area(&self) -> &Rect {
+    &self.area
+  }
+
+  fn visible_rows(&self) -> usize {
+    (self.area.height() / cell_size()).max(1)
+  }
+  fn row_count(&self) -> usize {
+    self.buttons.len().div_ceil(PICKER_COLUMNS)
+  }
+
+  fn update_scroll(&mut self) {
+    if !self.area.contains(&mouse_position()) {
+      return;
+    }
+    let wheel = mouse_wheel().1;
+    let max_first_row = self.row_count().saturating_sub(self.visible_rows());
+    if wheel > 0.0 {
+      self.first_row = self.first_row.saturating_sub(1);
+    } else if wheel < 0.0 {
+      self.first_row = (self.first_row + 1).min(max_first_row);
+    }
+  }
+
+  /** Draws the visible thumbnails and returns the kind of the one clicked, if any. */
+  pub fn draw(
+    &mut self,
+    deb: &DebugState,
+    assets: &mut Assets,
+    selections: &Selections,
+  ) -> Option<ButtonKind> {
+    self.update_scroll();
+    draw_rectangle(
+      self.area.left as f32,
+      self.area.top as f32,
+      self.area.width() as f32,
+      self.area.height() as f32,
+      PICKER_BACKGROUND,
+    );
+
+    let first = self.first_row * PICKER_COLUMNS;
+    let last = (first + self.visible_rows() * PICKER_COLUMNS).min(self.buttons.len());
+    let mut hit = None;
+    for (slot, button) in self.buttons[first..last].iter_mut().enumerate() {
+      button.set_position(
+        self.area.left + (slot % PICKER_COLUMNS) * cell_size(),
+        self.area.top + (slot / PICKER_COLUMNS) * cell_size(),
+      );
+      let selected = selections.is_selected(button.kind());
+      let state = button.draw(
+        deb,
+        assets,
+        selected,
+        (selections.foreground(), selections.background()),
+      );
+      if state.hit {
+        hit = Some(*button.kind());
+      }
+    }
+    hit
+  }
+}
+
+fn cell_size() -> usize {
+  THUMBNAIL_SIZE + THUMBNAIL_MARGIN * 2
+}
+
+/** Every sprite, group variant and nine group sheet, in that order. */
+fn picker_kinds(assets: &Assets) -> Vec<ButtonKind> {
+  let mut kinds: Vec<ButtonKind> = SpriteId::iter().map(ButtonKind::Sprite).collect();
+  for sg in SpriteGroup::iter() {
+    kinds.push(ButtonKind::SpriteGroup(sg, None));
+    for i in 0..assets.group_variant_count(&sg) {
+      kinds.push(ButtonKind::SpriteGroup(sg, Some(i as u8)));
+    }
+  }
+  for sg in SpriteNineGroup::iter() {
+    let mut indexes: Vec<u8> = assets.sprites.nine_slice_sprites[sg.index()]
+      .keys()
+      .copied()
+      .collect();
+    indexes.sort();
+    kinds.extend(indexes.into_iter().map(|i| ButtonKind::SpriteNine(sg, i)));
+  }
+  kinds
+}
@@ -40,6 +40,17 @@ impl Rect {
     }
   }
 
+  /** Returns a Rect of the same size with its top left corner at left,top. */
+  pub fn moved_to(&self, left: usize, top: usize) -> Rect {
+    Rect::new(left, top, left + self.width(), top + self.height())
+  }
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    pos.0 >= self.left as f32
+      && pos.0 <= self.right as f32
+      && pos.1 >= self.top as f32
+      && pos.1 <= self.bottom as f32
+  }
+
   pub fn width(&self) -> usize {
     self.right - self.left
   }
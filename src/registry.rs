@@ -0,0 +1,429 @@
+use std::{ops::RangeInclusive, str::FromStr, sync::OnceLock};
+
+use strum::{Display, EnumString};
+
+use crate::palette::PaletteName;
+
+pub const MANIFEST_NAME: &str = "sprites.manifest";
+/** Alpha values of world.png pixels holding a sprite group, see `WorldPixelAlpha`. */
+pub const GROUP_ALPHA: RangeInclusive<u8> = 150..=199;
+/** Alpha values of world.png pixels holding a nine group. */
+pub const NINE_ALPHA: RangeInclusive<u8> = 200..=254;
+const NO_CODE: &str = "-";
+//The first specific sprites are used by the game itself, see `SpriteId::EMPTY`.
+const BUILT_IN_SPRITES: [&str; 2] = ["Empty", "Missing"];
+//world.png stores a sprite id in one channel, and a group variant plus one for auto.
+const MAX_SPRITES: usize = 256;
+const MAX_GROUP_VARIANTS: usize = 255;
+const MAX_NINE_SHEETS: usize = 256;
+
+static REGISTRY: OnceLock<SpriteRegistry> = OnceLock::new();
+
+/*
+The manifest in the textures folder lists every sprite, one per line. Ids follow the line order.
+
+  <kind> <name> <frames> <code> <foreground> <background> [file]
+
+kind is `specific`, `group` or `nine`.
+frames is the number of images, `file_1.png` to `file_<frames>.png`. A single frame may also be
+  `file.png`. For nine groups every frame is a sheet.
+code is how world.png refers to the sprite. Specific sprites use a full pixel `r_g_b_a` or `-`,
+  groups and nine groups the alpha value their band starts at.
+foreground and background are the palettes used when world.png does not give any.
+file defaults to the name.
+Empty lines and lines starting with `#` are ignored.
+*/
+
+#[derive(Debug, PartialEq, Clone, Copy, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum SpriteKind {
+  Specific,
+  Group,
+  Nine,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColourCode {
+  Pixel([u8; 4]),
+  Alpha(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct SpriteDef {
+  pub name: String,
+  pub kind: SpriteKind,
+  pub frames: usize,
+  pub code: Option<ColourCode>,
+  pub foreground: PaletteName,
+  pub background: PaletteName,
+  pub file: String,
+}
+impl SpriteDef {
+  pub fn pixel(&self) -> Option<[u8; 4]> {
+    match self.code {
+      Some(ColourCode::Pixel(pixel)) => Some(pixel),
+      _ => None,
+    }
+  }
+  pub fn alpha(&self) -> Option<u8> {
+    match self.code {
+      Some(ColourCode::Alpha(alpha)) => Some(alpha),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct SpriteRegistry {
+  sprites: Vec<SpriteDef>,
+  groups: Vec<SpriteDef>,
+  nines: Vec<SpriteDef>,
+}
+
+impl SpriteRegistry {
+  /** Parses and validates a manifest. Every problem found is reported, one per line. */
+  pub fn parse(data: &str) -> Result<SpriteRegistry, String> {
+    let mut registry = SpriteRegistry::default();
+    let mut errors = vec![];
+    for (i, line) in data.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      match parse_def(line, i + 1) {
+        Ok(def) => registry.defs_mut(def.kind).push(def),
+        Err(e) => errors.push(e),
+      }
+    }
+    errors.extend(registry.validate());
+
+    if errors.is_empty() {
+      Ok(registry)
+    } else {
+      Err(format!(
+        "Invalid sprite manifest:\n  {}",
+        errors.join("\n  ")
+      ))
+    }
+  }
+
+  fn validate(&self) -> Vec<String> {
+    let mut errors = vec![];
+    for (i, name) in BUILT_IN_SPRITES.iter().enumerate() {
+      if self.sprites.get(i).map(|d| d.name.as_str()) != Some(name) {
+        errors.push(format!(
+          "The specific sprite number {} must be the built in '{}'.",
+          i + 1,
+          name
+        ));
+      }
+    }
+    if self.sprites.len() > MAX_SPRITES {
+      errors.push(format!(
+        "There are {} specific sprites, at most {} are supported.",
+        self.sprites.len(),
+        MAX_SPRITES
+      ));
+    }
+    for kind in [SpriteKind::Specific, SpriteKind::Group, SpriteKind::Nine] {
+      let defs = self.defs(kind);
+      for (i, def) in defs.iter().enumerate() {
+        if let Some(other) = defs[..i].iter().find(|d| d.name == def.name) {
+          errors.push(format!("Duplicate {} name '{}'.", kind, other.name));
+        }
+        if let Some(other) = defs[..i]
+          .iter()
+          .find(|d| def.code.is_some() && d.code == def.code)
+        {
+          errors.push(format!(
+            "'{}' and '{}' have the same colour code.",
+            other.name, def.name
+          ));
+        }
+        if def.frames == 0 {
+          errors.push(format!("'{}' needs at least one frame.", def.name));
+        }
+        let max_frames = match kind {
+          SpriteKind::Specific => None,
+          SpriteKind::Group => Some(MAX_GROUP_VARIANTS),
+          SpriteKind::Nine => Some(MAX_NINE_SHEETS),
+        };
+        if let Some(max_frames) = max_frames.filter(|max| def.frames > *max) {
+          errors.push(format!(
+            "'{}' has {} frames, at most {} are supported.",
+            def.name, def.frames, max_frames
+          ));
+        }
+      }
+    }
+    errors
+  }
+
+  fn defs(&self, kind: SpriteKind) -> &Vec<SpriteDef> {
+    match kind {
+      SpriteKind::Specific => &self.sprites,
+      SpriteKind::Group => &self.groups,
+      SpriteKind::Nine => &self.nines,
+    }
+  }
+  fn defs_mut(&mut self, kind: SpriteKind) -> &mut Vec<SpriteDef> {
+    match kind {
+      SpriteKind::Specific => &mut self.sprites,
+      SpriteKind::Group => &mut self.groups,
+      SpriteKind::Nine => &mut self.nines,
+    }
+  }
+
+  pub fn sprites(&self) -> &[SpriteDef] {
+    &self.sprites
+  }
+  pub fn groups(&self) -> &[SpriteDef] {
+    &self.groups
+  }
+  pub fn nines(&self) -> &[SpriteDef] {
+    &self.nines
+  }
+
+  /** Index of the specific sprite with exactly this colour code. */
+  pub fn sprite_by_pixel(&self, pixel: &[u8; 4]) -> Option<usize> {
+    self.sprites.iter().position(|d| d.pixel() == Some(*pixel))
+  }
+  /** Index of the group whose band holds `alpha`, bands run up to the next group's code. */
+  pub fn group_by_alpha(&self, alpha: &u8) -> Option<usize> {
+    band_at(&self.groups, alpha)
+  }
+  pub fn nine_by_alpha(&self, alpha: &u8) -> Option<usize> {
+    band_at(&self.nines, alpha)
+  }
+}
+
+fn band_at(defs: &[SpriteDef], alpha: &u8) -> Option<usize> {
+  defs
+    .iter()
+    .enumerate()
+    .filter_map(|(i, d)| d.alpha().filter(|a| a <= alpha).map(|a| (i, a)))
+    .max_by_key(|(_, a)| *a)
+    .map(|(i, _)| i)
+}
+
+fn parse_def(line: &str, line_nr: usize) -> Result<SpriteDef, String> {
+  let mut tokens = line.split_whitespace();
+  let mut next = |what: &str| {
+    tokens
+      .next()
+      .ok_or_else(|| format!("Line {}: missing {}.", line_nr, what))
+  };
+  let invalid =
+    |what: &str, token: &str| format!("Line {}: invalid {} '{}'.", line_nr, what, token);
+
+  let kind_token = next("kind")?;
+  let kind = SpriteKind::from_str(kind_token).map_err(|_| invalid("kind", kind_token))?;
+  let name = next("name")?.to_owned();
+  let frames_token = next("frame count")?;
+  let frames = frames_token
+    .parse()
+    .map_err(|_| invalid("frame count", frames_token))?;
+  let code_token = next("colour code")?;
+  let code = parse_code(kind, code_token).ok_or_else(|| invalid("colour code", code_token))?;
+  let fg_token = next("foreground")?;
+  let foreground = PaletteName::from_str(fg_token).map_err(|_| invalid("foreground", fg_token))?;
+  let bg_token = next("background")?;
+  let background = PaletteName::from_str(bg_token).map_err(|_| invalid("background", bg_token))?;
+  let file = next("file").map(|f| f.to_owned()).unwrap_or(name.clone());
+  if next("").is_ok() {
+    return Err(format!("Line {}: trailing data after '{}'.", line_nr, name));
+  }
+
+  Ok(SpriteDef {
+    name,
+    kind,
+    frames,
+    code,
+    foreground,
+    background,
+    file,
+  })
+}
+
+/** `None` when the code is malformed or outside the band of its kind. */
+fn parse_code(kind: SpriteKind, token: &str) -> Option<Option<ColourCode>> {
+  match kind {
+    SpriteKind::Specific => {
+      if token == NO_CODE {
+        return Some(None);
+      }
+      let channels: Vec<u8> = token
+        .split('_')
+        .map(|c| c.parse().ok())
+        .collect::<Option<_>>()?;
+      let pixel: [u8; 4] = channels.try_into().ok()?;
+      //Exact codes win over the bands, so they must not reach into them.
+      if pixel[3] >= *GROUP_ALPHA.start() {
+        return None;
+      }
+      Some(Some(ColourCode::Pixel(pixel)))
+    }
+    SpriteKind::Group | SpriteKind::Nine => {
+      let alpha: u8 = token.parse().ok()?;
+      let band = if kind == SpriteKind::Group {
+        GROUP_ALPHA
+      } else {
+        NINE_ALPHA
+      };
+      band
+        .contains(&alpha)
+        .then_some(Some(ColourCode::Alpha(alpha)))
+    }
+  }
+}
+
+/** Makes the registry available to the sprite ids. Can only be done once. */
+pub fn init_registry(registry: SpriteRegistry) {
+  if REGISTRY.set(registry).is_err() {
+    panic!("The sprite registry is already loaded.");
+  }
+}
+
+/** Loads the manifest from the assets for tests that need sprite ids, as often as they like. */
+#[cfg(test)]
+pub fn init_test_registry() {
+  REGISTRY.get_or_init(|| {
+    SpriteRegistry::parse(include_str!("../assets/textures/sprites.manifest"))
+      .expect("The test manifest is invalid.")
+  });
+}
+
+pub fn registry() -> &'static SpriteRegistry {
+  REGISTRY
+    .get()
+    .expect("The sprite registry is used before it is loaded.")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BUILT_IN: &str = "specific Empty 1 000_000_000_000 White White
+specific Missing 1 255_255_255_000 White White missing
+";
+
+  fn parse(lines: &str) -> Result<SpriteRegistry, String> {
+    SpriteRegistry::parse(&format!("{}{}", BUILT_IN, lines))
+  }
+
+  #[test]
+  fn parses_a_manifest() {
+    let registry = parse(
+      "# comment
+
+group Tree 7 150 White Green
+nine Trees 6 200 Blue White sheet
+",
+    )
+    .unwrap();
+    assert_eq!(registry.sprites().len(), 2);
+    assert_eq!(registry.sprites()[1].file, "missing");
+    assert_eq!(registry.sprites()[1].pixel(), Some([255, 255, 255, 0]));
+    let tree = &registry.groups()[0];
+    assert_eq!(tree.name, "Tree");
+    assert_eq!(tree.frames, 7);
+    assert_eq!(tree.file, "Tree");
+    assert_eq!(tree.background, PaletteName::Green);
+    assert_eq!(registry.nines()[0].file, "sheet");
+    assert_eq!(registry.group_by_alpha(&160), Some(0));
+    assert_eq!(registry.nine_by_alpha(&199), None);
+  }
+
+  #[test]
+  fn needs_the_built_in_sprites_first() {
+    let errors = SpriteRegistry::parse("specific Missing 1 - White White").unwrap_err();
+    assert!(errors.contains("number 1 must be the built in 'Empty'"));
+    assert!(errors.contains("number 2 must be the built in 'Missing'"));
+    assert!(SpriteRegistry::parse("").is_err());
+  }
+
+  #[test]
+  fn rejects_duplicates() {
+    let errors = parse("group Tree 1 150 White White\ngroup Tree 1 160 White White").unwrap_err();
+    assert!(errors.contains("Duplicate group name 'Tree'."));
+    let errors = parse("nine A 1 200 White White\nnine B 1 200 White White").unwrap_err();
+    assert!(errors.contains("'A' and 'B' have the same colour code."));
+    let errors = parse("specific Rock 1 000_000_000_000 White White").unwrap_err();
+    assert!(errors.contains("'Empty' and 'Rock' have the same colour code."));
+    //Sprites without a code don't clash.
+    assert!(parse("specific A 1 - White White\nspecific B 1 - White White").is_ok());
+    //Names only need to be unique within their kind.
+    assert!(parse("group Tree 1 150 White White\nnine Tree 1 200 White White").is_ok());
+  }
+
+  #[test]
+  fn rejects_frame_counts_out_of_range() {
+    let errors = parse("group Tree 0 150 White White").unwrap_err();
+    assert!(errors.contains("'Tree' needs at least one frame."));
+    assert!(parse("group Tree 255 150 White White").is_ok());
+    assert!(parse("group Tree 256 150 White White").is_err());
+    assert!(parse("nine Trees 256 200 White White").is_ok());
+    assert!(parse("nine Trees 257 200 White White").is_err());
+  }
+
+  #[test]
+  fn rejects_too_many_sprites() {
+    let sprites = |count: usize| {
+      (BUILT_IN_SPRITES.len()..count)
+        .map(|i| format!("specific S{} 1 - White White\n", i))
+        .collect::<String>()
+    };
+    assert!(parse(&sprites(MAX_SPRITES)).is_ok());
+    let errors = parse(&sprites(MAX_SPRITES + 1)).unwrap_err();
+    assert!(errors.contains("There are 257 specific sprites"));
+  }
+
+  #[test]
+  fn rejects_malformed_lines() {
+    for (line, error) in [
+      (
+        "sprite Rock 1 - White White",
+        "Line 3: invalid kind 'sprite'.",
+      ),
+      ("specific Rock", "Line 3: missing frame count."),
+      (
+        "specific Rock x - White White",
+        "Line 3: invalid frame count 'x'.",
+      ),
+      (
+        "specific Rock 1 1_2_3 White White",
+        "Line 3: invalid colour code '1_2_3'.",
+      ),
+      (
+        "specific Rock 1 1_2_3_150 White White",
+        "invalid colour code '1_2_3_150'.",
+      ),
+      (
+        "group Tree 1 200 White White",
+        "Line 3: invalid colour code '200'.",
+      ),
+      (
+        "nine Trees 1 150 White White",
+        "Line 3: invalid colour code '150'.",
+      ),
+      (
+        "specific Rock 1 - Pink White",
+        "Line 3: invalid foreground 'Pink'.",
+      ),
+      ("specific Rock 1 - White", "Line 3: missing background."),
+      (
+        "specific Rock 1 - White White rock x",
+        "Line 3: trailing data after 'Rock'.",
+      ),
+    ] {
+      let errors = parse(line).unwrap_err();
+      assert!(errors.contains(error), "{}: {}", line, errors);
+    }
+  }
+
+  #[test]
+  fn the_shipped_manifest_is_valid() {
+    let registry = SpriteRegistry::parse(include_str!("../assets/textures/sprites.manifest"));
+    assert!(registry.is_ok(), "{}", registry.unwrap_err());
+  }
+}
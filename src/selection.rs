@@ -0,0 +1,330 @@
+use macroquad::{prelude::*, rand::gen_range};
+
+use crate::{
+  assets::Assets,
+  atlas::TileBatch,
+  interaction::Hit,
+  sprite::SpriteKey,
+  tile::Tile,
+  world::{World, TILE_SIZE},
+};
+
+const MARQUEE_COLOR: Color = Color::new(1.0, 1.0, 0.0, 0.9);
+const FLOATING_TINT: Color = Color::new(1.0, 1.0, 1.0, 0.25);
+
+/** Sprite keys of a rectangle of cells, row by row. Empty cells are `None`. */
+#[derive(Debug, Clone)]
+pub struct TileBlock {
+  pub width: usize,
+  pub height: usize,
+  keys: Vec<Option<SpriteKey>>,
+}
+
+impl TileBlock {
+  pub fn new(width: usize, height: usize, keys: Vec<Option<SpriteKey>>) -> TileBlock {
+    assert_eq!(keys.len(), width * height, "A block needs a key per cell.");
+    TileBlock {
+      width,
+      height,
+      keys,
+    }
+  }
+  /** Copies a rectangle of the active layer, it has to be inside the world. */
+  pub fn from_world(
+    world: &World,
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+  ) -> TileBlock {
+    let keys = (top..top + height)
+      .flat_map(|y| (left..left + width).map(move |x| (x, y)))
+      .map(|(x, y)| world.get_tile(&x, &y).map(|t| t.get_sprite_key().clone()))
+      .collect();
+    TileBlock {
+      width,
+      height,
+      keys,
+    }
+  }
+  pub fn get(&self, x: usize, y: usize) -> Option<&SpriteKey> {
+    self.keys[y * self.width + x].as_ref()
+  }
+
+  pub fn flip_horizontal(&mut self) {
+    for row in self.keys.chunks_mut(self.width) {
+      row.reverse();
+    }
+  }
+  pub fn flip_vertical(&mut self) {
+    let rows: Vec<&[Option<SpriteKey>]> = self.keys.chunks(self.width).rev().collect();
+    self.keys = rows.concat();
+  }
+  /** A quarter turn clockwise, width and height swap. */
+  pub fn rotate(&mut self) {
+    let keys = (0..self.width)
+      .flat_map(|y| (0..self.height).map(move |x| (x, y)))
+      .map(|(x, y)| self.get(y, self.height - 1 - x).cloned())
+      .collect();
+    self.keys = keys;
+    std::mem::swap(&mut self.width, &mut self.height);
+  }
+
+  /** A copy where every group tile shows a random variant, so repeated stamps differ. */
+  pub fn with_random_variants(&self, assets: &Assets) -> TileBlock {
+    let mut block = self.clone();
+    for key in block.keys.iter_mut().flatten() {
+      if let SpriteKey::Group(group) = key {
+        let count = assets.group_variant_count(&group.sg);
+        if count > 0 {
+          group.sg_index = Some(gen_range(0, count) as u8);
+        }
+      }
+    }
+    block
+  }
+
+  /**
+  Keys to set for the block with its top left cell at `left`,`top`. Empty cells of the block
+  leave the world as it is and cells outside the world are dropped.
+  */
+  pub fn placed(
+    &self,
+    world: &World,
+    left: isize,
+    top: isize,
+  ) -> Vec<((usize, usize), Option<SpriteKey>)> {
+    (0..self.height)
+      .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+      .filter_map(|(x, y)| {
+        let key = self.get(x, y)?.clone();
+        let cell = world.cell_in_world((left + x as isize, top + y as isize))?;
+        Some((cell, Some(key)))
+      })
+      .collect()
+  }
+}
+
+/** A block following the mouse until it is placed. */
+struct Floating {
+  block: TileBlock,
+  //Cell of the block under the mouse.
+  grab: (isize, isize),
+  //History label of placing it.
+  label: &'static str,
+  //Where a moved block came from and how it looked, it goes back there when the move is
+  //cancelled. `None` for a paste.
+  lifted_from: Option<((isize, isize), TileBlock)>,
+}
+
+/** Marquee over the active layer, the clipboard and the block being pasted or moved. */
+#[derive(Default)]
+pub struct Selection {
+  //Corners of the marquee in the order they were dragged, both included.
+  area: Option<((usize, usize), (usize, usize))>,
+  selecting: bool,
+  floating: Option<Floating>,
+  clipboard: Option<TileBlock>,
+}
+
+impl Selection {
+  pub fn is_floating(&self) -> bool {
+    self.floating.is_some()
+  }
+  /** Label for the history entry of placing the floating block. */
+  pub fn action_label(&self) -> &'static str {
+    self.floating.as_ref().map_or("Move", |f| f.label)
+  }
+
+  /** Left, top, width and height of the marquee, cut to the world since it may have shrunk. */
+  fn area_rect(&self, world: &World) -> Option<(usize, usize, usize, usize)> {
+    let (a, b) = self.area?;
+    let (left, top) = (a.0.min(b.0), a.1.min(b.1));
+    if left >= world.width || top >= world.height {
+      return None;
+    }
+    let right = a.0.max(b.0).min(world.width - 1);
+    let bottom = a.1.max(b.1).min(world.height - 1);
+    Some((left, top, right - left + 1, bottom - top + 1))
+  }
+
+  /** Tiles under the marquee. */
+  pub fn selected_block(&self, world: &World) -> Option<TileBlock> {
+    let (left, top, width, height) = self.area_rect(world)?;
+    Some(TileBlock::from_world(world, left, top, width, height))
+  }
+  /** Copies the marquee to the clipboard. Returns the size of the copied block. */
+  pub fn copy(&mut self, world: &World) -> Option<(usize, usize)> {
+    let block = self.selected_block(world)?;
+    let size = (block.width, block.height);
+    self.clipboard = Some(block);
+    Some(size)
+  }
+  /** Copies the marquee to the clipboard and empties it. */
+  pub fn cut(&mut self, world: &World) -> Option<Hit> {
+    self.copy(world)?;
+    Some(Hit::Keys(self.area_cells(world)))
+  }
+  /** Floats the clipboard under the mouse, see `float`. */
+  pub fn paste(&mut self) {
+    if let Some(block) = self.clipboard.clone() {
+      self.float(block, "Paste");
+    }
+  }
+  /** Floats a block under the mouse, held by its middle, until a click places it. */
+  pub fn float(&mut self, block: TileBlock, label: &'static str) {
+    self.floating = Some(Floating {
+      grab: ((block.width / 2) as isize, (block.height / 2) as isize),
+      block,
+      label,
+      lifted_from: None,
+    });
+  }
+  fn area_cells(&self, world: &World) -> Vec<((usize, usize), Option<SpriteKey>)> {
+    let Some((left, top, width, height)) = self.area_rect(world) else {
+      return vec![];
+    };
+    (top..top + height)
+      .flat_map(|y| (left..left + width).map(move |x| ((x, y), None)))
+      .collect()
+  }
+
+  /** H and V flip the floating block, T turns it. */
+  pub fn update_keys(&mut self) {
+    let Some(floating) = &mut self.floating else {
+      return;
+    };
+    let (width, height) = (
+      floating.block.width as isize,
+      floating.block.height as isize,
+    );
+    let (x, y) = floating.grab;
+    if is_key_pressed(KeyCode::H) {
+      floating.block.flip_horizontal();
+      floating.grab = (width - 1 - x, y);
+    }
+    if is_key_pressed(KeyCode::V) {
+      floating.block.flip_vertical();
+      floating.grab = (x, height - 1 - y);
+    }
+    if is_key_pressed(KeyCode::T) {
+      floating.block.rotate();
+      floating.grab = (height - 1 - y, x);
+    }
+  }
+
+  /**
+  Drag to select, drag from inside the marquee to move its tiles. A pasted block is placed
+  with a click, a moved one when the button is released. Escape cancels either, a cancelled
+  move puts the tiles back.
+  */
+  pub fn update(&mut self, world: &World, pressed: bool) -> Option<Hit> {
+    let cell = world.screen_to_cell(&world.mouse_pos);
+    if let Some(floating) = &self.floating {
+      if is_key_pressed(KeyCode::Escape) {
+        let (pos, block) = self.floating.take()?.lifted_from?;
+        return Some(Hit::Keys(block.placed(world, pos.0, pos.1)));
+      }
+      let place = match floating.lifted_from {
+        Some(_) => !is_mouse_button_down(MouseButton::Left),
+        None => pressed,
+      };
+      if !place || !world.is_active_layer_editable() {
+        return None;
+      }
+      let floating = self.floating.take()?;
+      let (left, top) = (cell.0 - floating.grab.0, cell.1 - floating.grab.1);
+      //The marquee moves along, so the block can be picked up again.
+      let corner = |x: isize, y: isize| {
+        (
+          x.clamp(0, world.width as isize - 1) as usize,
+          y.clamp(0, world.height as isize - 1) as usize,
+        )
+      };
+      self.area = Some((
+        corner(left, top),
+        corner(
+          left + floating.block.width as isize - 1,
+          top + floating.block.height as isize - 1,
+        ),
+      ));
+      return Some(Hit::Keys(floating.block.placed(world, left, top)));
+    }
+
+    if is_key_pressed(KeyCode::Escape) {
+      self.area = None;
+    }
+    if pressed {
+      if let Some((left, top, width, height)) = self.area_rect(world) {
+        let inside = (left as isize..(left + width) as isize).contains(&cell.0)
+          && (top as isize..(top + height) as isize).contains(&cell.1);
+        if inside && world.is_active_layer_editable() {
+          let block = TileBlock::from_world(world, left, top, width, height);
+          self.floating = Some(Floating {
+            block: block.clone(),
+            grab: (cell.0 - left as isize, cell.1 - top as isize),
+            label: "Move",
+            lifted_from: Some(((left as isize, top as isize), block)),
+          });
+          return Some(Hit::Keys(self.area_cells(world)));
+        }
+      }
+      self.area = world.cell_in_world(cell).map(|c| (c, c));
+      self.selecting = self.area.is_some();
+    }
+    if self.selecting {
+      if let (Some(area), Some(end)) = (&mut self.area, world.px_to_grid(None)) {
+        area.1 = end;
+      }
+      self.selecting = is_mouse_button_down(MouseButton::Left);
+    }
+    None
+  }
+
+  pub fn draw_marquee(&self, world: &World) {
+    let Some((left, top, width, height)) = self.area_rect(world) else {
+      return;
+    };
+    let pos = world
+      .camera
+      .world_to_screen(vec2((left * TILE_SIZE) as f32, (top * TILE_SIZE) as f32));
+    let size = TILE_SIZE as f32 * world.camera.zoom;
+    draw_rectangle_lines(
+      pos.x,
+      pos.y,
+      width as f32 * size,
+      height as f32 * size,
+      2.0,
+      MARQUEE_COLOR,
+    );
+  }
+
+  /** Draws the floating block where it would be placed, over the world. */
+  pub fn draw_floating(&self, world: &World, assets: &mut Assets) {
+    let Some(floating) = &self.floating else {
+      return;
+    };
+    let cell = world.screen_to_cell(&world.mouse_pos);
+    let (left, top) = (cell.0 - floating.grab.0, cell.1 - floating.grab.1);
+    let mut batch = TileBatch::default();
+    for ((x, y), key) in floating.block.placed(world, left, top) {
+      if let Some(key) = key {
+        let tile = Tile::from_sprite_key(x, y, key);
+        tile.draw(assets, &mut batch, &world.camera, world.active_layer() == 0);
+      }
+    }
+    batch.submit();
+
+    let pos = world.camera.world_to_screen(vec2(
+      (left * TILE_SIZE as isize) as f32,
+      (top * TILE_SIZE as isize) as f32,
+    ));
+    let size = TILE_SIZE as f32 * world.camera.zoom;
+    let (width, height) = (
+      floating.block.width as f32 * size,
+      floating.block.height as f32 * size,
+    );
+    draw_rectangle(pos.x, pos.y, width, height, FLOATING_TINT);
+    draw_rectangle_lines(pos.x, pos.y, width, height, 2.0, MARQUEE_COLOR);
+  }
+}
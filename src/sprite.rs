@@ -1,9 +1,16 @@
+use std::{fmt, str::FromStr};
+
 use enum_map::Enum;
 use strum::{Display, EnumIter, EnumString};
 
-use crate::{palette::PaletteName, tile::Tile};
+use crate::{
+  autotile::BlobMask,
+  palette::PaletteName,
+  registry::{registry, SpriteDef},
+};
 
-#[derive(Debug, PartialEq, Clone, Copy, Enum, Hash, Eq, EnumIter)]
+/** Pieces of a classic 3x3 nine-slice sheet, see `BlobMask::to_nine_slice_dir`. */
+#[derive(Debug, PartialEq, Clone, Copy, Enum, Hash, Eq, EnumIter, Display, EnumString)]
 pub enum NineSliceDir {
   LeftUp,
   Up,
@@ -16,113 +23,55 @@ pub enum NineSliceDir {
   RightDown,
 }
 
-impl NineSliceDir {
-  pub fn get_dir_for_nine_tile(
-    tiles: &Vec<Option<&Tile>>,
-    nine_group: &SpriteNineGroup,
-  ) -> NineSliceDir {
-    let indexes = if tiles.len() == 9 {
-      [1, 3, 5, 7]
-    } else if tiles.len() == 4 {
-      [0, 1, 2, 3]
-    } else {
-      panic!("Invalid tile count for checking nine slice direction.");
-    };
-
-    let above = if let Some(t) = tiles[indexes[0]] {
-      match &t.get_sprite_key() {
-        &SpriteKey::Nine(nine) => &nine.sg == nine_group,
-        _ => false,
+/**
+Generates an id into one of the lists of the sprite registry. Ids print as and parse from the
+name in the manifest, so map files stay readable.
+*/
+macro_rules! registry_id {
+  ($id:ident, $defs:ident, $what:literal) => {
+    #[derive(Debug, PartialEq, Clone, Copy, Hash, Eq)]
+    pub struct $id(u16);
+    impl $id {
+      pub fn index(&self) -> usize {
+        self.0 as usize
       }
-    } else {
-      false
-    };
-
-    let left = if let Some(t) = tiles[indexes[1]] {
-      match &t.get_sprite_key() {
-        &SpriteKey::Nine(nine) => &nine.sg == nine_group,
-        _ => false,
+      pub fn from_index(index: usize) -> Option<$id> {
+        (index < registry().$defs().len()).then_some($id(index as u16))
       }
-    } else {
-      false
-    };
-
-    let right = if let Some(t) = tiles[indexes[2]] {
-      match &t.get_sprite_key() {
-        &SpriteKey::Nine(nine) => &nine.sg == nine_group,
-        _ => false,
+      pub fn iter() -> impl Iterator<Item = $id> {
+        (0..registry().$defs().len()).map(|i| $id(i as u16))
       }
-    } else {
-      false
-    };
-
-    let below = if let Some(t) = tiles[indexes[3]] {
-      match &t.get_sprite_key() {
-        &SpriteKey::Nine(nine) => &nine.sg == nine_group,
-        _ => false,
+      pub fn def(&self) -> &'static SpriteDef {
+        &registry().$defs()[self.index()]
       }
-    } else {
-      false
-    };
-
-    if above && left && right && below {
-      return NineSliceDir::Center;
-    }
-    if !above && !left && right && below {
-      return NineSliceDir::LeftUp;
     }
-    if !above && !left && !right && below {
-      return NineSliceDir::Up;
-    }
-    if !above && left && !right && below {
-      return NineSliceDir::RightUp;
-    }
-    if !above && left && !right && !below {
-      return NineSliceDir::Right;
-    }
-    if above && left && !right && !below {
-      return NineSliceDir::RightDown;
-    }
-    if above && !left && !right && !below {
-      return NineSliceDir::Down;
-    }
-    if above && !left && right && !below {
-      return NineSliceDir::LeftDown;
+    impl fmt::Display for $id {
+      fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.def().name)
+      }
     }
-    if !above && !left && right && below {
-      return NineSliceDir::Left;
+    impl FromStr for $id {
+      type Err = String;
+      fn from_str(s: &str) -> Result<Self, Self::Err> {
+        registry()
+          .$defs()
+          .iter()
+          .position(|d| d.name == s)
+          .map(|i| $id(i as u16))
+          .ok_or_else(|| format!("Unknown {} '{}'.", $what, s))
+      }
     }
-    panic!("Did not find nine tile direction.");
-  }
+  };
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Enum, Hash, Eq, EnumIter, Display)]
-pub enum SpriteNineGroup {
-  Trees,
-  Mounds,
-}
-#[derive(Debug, PartialEq, Clone, Copy, Enum, Hash, Eq, EnumIter, Display)]
-pub enum SpriteGroup {
-  Tree,
-  Mound,
-}
-#[derive(Debug, PartialEq, Clone, Copy, Enum, Hash, Eq, EnumIter)]
-pub enum SpriteId {
-  Empty,
-  Missing,
-}
-#[derive(Debug, PartialEq, Clone, Copy, Enum, EnumString)]
-pub enum SpriteClrId {
-  C000_000_000_000,
-  C255_255_255_000,
-}
+registry_id!(SpriteId, sprites, "sprite");
+registry_id!(SpriteGroup, groups, "sprite group");
+registry_id!(SpriteNineGroup, nines, "nine group");
 
-pub fn clr_to_sid(clr: SpriteClrId) -> SpriteId {
-  SpriteId::from_usize(SpriteClrId::into_usize(clr))
+impl SpriteId {
+  pub const EMPTY: SpriteId = SpriteId(0);
+  pub const MISSING: SpriteId = SpriteId(1);
 }
-// pub fn sid_to_clr(sid: SpriteId) -> SpriteClrId {
-//   SpriteClrId::from_usize(SpriteId::into_usize(sid))
-// }
 
 #[derive(Debug, PartialEq, Hash, Eq, Clone)]
 pub enum SpriteKey {
@@ -154,6 +103,22 @@ impl SpriteKey {
       SpriteKey::Nine(n) => &n.background,
     }
   }
+  pub fn get_fg(&self) -> &PaletteName {
+    match self {
+      SpriteKey::Specific(s) => &s.foreground,
+      SpriteKey::Group(g) => &g.foreground,
+      SpriteKey::Nine(n) => &n.foreground,
+    }
+  }
+  /** Same sprite, group variant or nine group sheet, whatever the palettes and blob mask. */
+  pub fn same_sprite(&self, other: &SpriteKey) -> bool {
+    match (self, other) {
+      (SpriteKey::Specific(a), SpriteKey::Specific(b)) => a.sid == b.sid,
+      (SpriteKey::Group(a), SpriteKey::Group(b)) => a.sg == b.sg && a.sg_index == b.sg_index,
+      (SpriteKey::Nine(a), SpriteKey::Nine(b)) => a.sg == b.sg && a.sg_index == b.sg_index,
+      _ => false,
+    }
+  }
 }
 
 #[derive(Debug, PartialEq, Hash, Eq, Clone)]
@@ -185,14 +150,15 @@ pub struct SpriteGroupPaletteKey {
   pub foreground: PaletteName,
   pub background: PaletteName,
   pub sg: SpriteGroup,
-  pub sg_index: u8,
+  /** `None` lets the tile pick a variant from its position. */
+  pub sg_index: Option<u8>,
 }
 impl SpriteGroupPaletteKey {
   pub fn new(
     foreground: PaletteName,
     background: PaletteName,
     sg: SpriteGroup,
-    sg_index: u8,
+    sg_index: Option<u8>,
   ) -> SpriteGroupPaletteKey {
     SpriteGroupPaletteKey {
       foreground,
@@ -215,7 +181,7 @@ impl From<&SpriteGroupPaletteKey> for SpriteGroupPaletteKey {
 
 #[derive(Debug, PartialEq, Hash, Eq, Clone)]
 pub struct SpriteNinePaletteKey {
-  pub nine_dir: NineSliceDir,
+  pub blob: BlobMask,
   pub foreground: PaletteName,
   pub background: PaletteName,
   pub sg_index: u8,
@@ -227,21 +193,21 @@ impl SpriteNinePaletteKey {
     background: PaletteName,
     sg: SpriteNineGroup,
     sg_index: u8,
-    nine_dir: NineSliceDir,
+    blob: BlobMask,
   ) -> SpriteNinePaletteKey {
     SpriteNinePaletteKey {
       foreground,
       background,
       sg_index,
       sg,
-      nine_dir,
+      blob,
     }
   }
 }
 impl From<&SpriteNinePaletteKey> for SpriteNinePaletteKey {
   fn from(other: &SpriteNinePaletteKey) -> Self {
     SpriteNinePaletteKey {
-      nine_dir: other.nine_dir,
+      blob: other.blob,
       foreground: other.foreground,
       background: other.background,
       sg_index: other.sg_index,
@@ -0,0 +1,269 @@
+use std::path::Path;
+
+use macroquad::{
+  hash,
+  prelude::{
+    is_key_pressed, is_mouse_button_pressed, mouse_position, mouse_wheel, vec2, Color, KeyCode,
+    MouseButton, Vec2, WHITE,
+  },
+  shapes::draw_rectangle,
+  text::{draw_text_ex, TextParams},
+  ui::{root_ui, widgets},
+  window::{screen_height, screen_width},
+};
+
+use crate::{
+  assets::Assets,
+  atlas::TileBatch,
+  loading,
+  rect::Rect,
+  selection::TileBlock,
+  ui::{FontWeight, Lang, UIHit},
+};
+
+const STAMP_THUMBNAIL_SIZE: usize = 48;
+const STAMP_MARGIN: usize = 4;
+const STAMP_LABEL_SIZE: u16 = 14;
+const PANEL_BACKGROUND: Color = Color::new(0.1, 0.1, 0.15, 0.6);
+const STAMP_FRAME_COLOR: Color = Color::new(0.2, 0.2, 0.25, 0.8);
+const STAMP_HOVER_COLOR: Color = Color::new(0.4, 0.4, 0.5, 0.9);
+const DIALOG_WIDTH: f32 = 300.0;
+const DIALOG_HEIGHT: f32 = 120.0;
+
+/** A saved block of tiles, see `map_file::serialize_stamp`. */
+pub struct Stamp {
+  pub name: String,
+  pub block: TileBlock,
+}
+
+/**
+List of the stamps on disk with thumbnails. Scrolls a stamp at a time with the mouse wheel.
+Kept apart from the `UiLayout` so the stamps and the scroll survive a layout rebuild.
+*/
+pub struct StampPanel {
+  stamps: Vec<Stamp>,
+  first: usize,
+}
+
+impl StampPanel {
+  pub fn new(stamps: Vec<Stamp>) -> StampPanel {
+    StampPanel { stamps, first: 0 }
+  }
+
+  pub fn stamp(&self, i: usize) -> Option<&Stamp> {
+    self.stamps.get(i)
+  }
+  /** Adds a stamp in name order, or replaces the one with the same name. */
+  pub fn add(&mut self, stamp: Stamp) {
+    match self.stamps.binary_search_by(|s| s.name.cmp(&stamp.name)) {
+      Ok(i) => self.stamps[i] = stamp,
+      Err(i) => self.stamps.insert(i, stamp),
+    }
+  }
+  /** Whether the screen position is over the panel placed in `available`. */
+  pub fn contains(&self, available: &Rect, pos: &(f32, f32)) -> bool {
+    !self.stamps.is_empty() && self.area(available).contains(pos)
+  }
+
+  //The panel fits its stamps, it grows down to `available.bottom` at most.
+  fn area(&self, available: &Rect) -> Rect {
+    let bottom = (available.top + self.stamps.len() * entry_height()).min(available.bottom);
+    Rect::new(
+      available.left,
+      available.top,
+      available.right,
+      bottom.max(available.top),
+    )
+  }
+  fn visible_count(area: &Rect) -> usize {
+    (area.height() / entry_height()).max(1)
+  }
+  fn update_scroll(&mut self, area: &Rect) {
+    let max_first = self
+      .stamps
+      .len()
+      .saturating_sub(StampPanel::visible_count(area));
+    //The panel may have shrunk since the last frame.
+    self.first = self.first.min(max_first);
+    if !area.contains(&mouse_position()) {
+      return;
+    }
+    let wheel = mouse_wheel().1;
+    if wheel > 0.0 {
+      self.first = self.first.saturating_sub(1);
+    } else if wheel < 0.0 {
+      self.first = (self.first + 1).min(max_first);
+    }
+  }
+
+  /**
+  Draws the visible stamps into `available`. Returns the stamp clicked, or a panel hit for clicks
+  between them.
+  */
+  pub fn draw(&mut self, assets: &mut Assets, available: &Rect) -> Option<UIHit> {
+    if self.stamps.is_empty() {
+      return None;
+    }
+    let area = self.area(available);
+    self.update_scroll(&area);
+    draw_rectangle(
+      area.left as f32,
+      area.top as f32,
+      area.width() as f32,
+      area.height() as f32,
+      PANEL_BACKGROUND,
+    );
+
+    let mouse = mouse_position();
+    let last = (self.first + StampPanel::visible_count(&area)).min(self.stamps.len());
+    let mut hit =
+      (area.contains(&mouse) && is_mouse_button_pressed(MouseButton::Left)).then_some(UIHit::Panel);
+    //Frames first, the thumbnails of every stamp go out in one batch.
+    let mut batch = TileBatch::default();
+    for (slot, i) in (self.first..last).enumerate() {
+      let top = area.top + slot * entry_height();
+      let entry = Rect::new(area.left, top, area.right, top + entry_height());
+      let hovered = entry.contains(&mouse);
+      if hovered && is_mouse_button_pressed(MouseButton::Left) {
+        hit = Some(UIHit::Stamp(i));
+      }
+      let thumbnail = Rect::new(
+        area.left + STAMP_MARGIN,
+        top + STAMP_MARGIN,
+        area.left + STAMP_MARGIN + STAMP_THUMBNAIL_SIZE,
+        top + STAMP_MARGIN + STAMP_THUMBNAIL_SIZE,
+      );
+      draw_rectangle(
+        thumbnail.left as f32,
+        thumbnail.top as f32,
+        thumbnail.width() as f32,
+        thumbnail.height() as f32,
+        if hovered {
+          STAMP_HOVER_COLOR
+        } else {
+          STAMP_FRAME_COLOR
+        },
+      );
+      push_thumbnail(assets, &mut batch, &self.stamps[i].block, &thumbnail);
+    }
+    batch.submit();
+
+    for (slot, stamp) in self.stamps[self.first..last].iter().enumerate() {
+      let top = area.top + slot * entry_height();
+      draw_text_ex(
+        &stamp.name,
+        (area.left + STAMP_THUMBNAIL_SIZE + STAMP_MARGIN * 3) as f32,
+        (top + entry_height() / 2) as f32 + STAMP_LABEL_SIZE as f32 / 3.0,
+        TextParams {
+          font: assets.fonts.get_font(&Lang::EnUs, &FontWeight::Regular),
+          font_size: STAMP_LABEL_SIZE,
+          color: WHITE,
+          ..Default::default()
+        },
+      );
+    }
+    hit
+  }
+}
+
+fn entry_height() -> usize {
+  STAMP_THUMBNAIL_SIZE + STAMP_MARGIN * 2
+}
+
+/** Scales the block to fit `area`, keeping its cells square. */
+fn push_thumbnail(assets: &mut Assets, batch: &mut TileBatch, block: &TileBlock, area: &Rect) {
+  let cell = area.width() as f32 / block.width.max(block.height) as f32;
+  let left = area.left as f32 + (area.width() as f32 - cell * block.width as f32) / 2.0;
+  let top = area.top as f32 + (area.height() as f32 - cell * block.height as f32) / 2.0;
+  for y in 0..block.height {
+    for x in 0..block.width {
+      if let Some(key) = block.get(x, y) {
+        batch.push(
+          assets.get_key_texture(key, &x, &y, true),
+          macroquad::prelude::Rect::new(left + x as f32 * cell, top + y as f32 * cell, cell, cell),
+        );
+      }
+    }
+  }
+}
+
+/** Asks for the name to save the selected tiles under. Modal while open. */
+#[derive(Default)]
+pub struct SaveStampDialog {
+  pub open: bool,
+  name: String,
+  error: Option<String>,
+  //Name of an existing stamp that the next save replaces.
+  replace: Option<String>,
+}
+
+impl SaveStampDialog {
+  pub fn show(&mut self) {
+    self.open = true;
+    self.error = None;
+    self.replace = None;
+  }
+
+  /** Draws the dialog while it is open. Returns the name once it is saved. */
+  pub fn draw(&mut self) -> Option<String> {
+    if !self.open {
+      return None;
+    }
+    let mut name = None;
+    let mut close = false;
+    let pos = vec2(
+      (screen_width() - DIALOG_WIDTH) / 2.0,
+      (screen_height() - DIALOG_HEIGHT) / 2.0,
+    );
+    widgets::Window::new(hash!(), pos, vec2(DIALOG_WIDTH, DIALOG_HEIGHT))
+      .label("Save stamp")
+      .movable(false)
+      .ui(&mut root_ui(), |ui| {
+        widgets::InputText::new(hash!())
+          .label("Name")
+          .ui(ui, &mut self.name);
+        if let Some(error) = &self.error {
+          ui.label(None, error);
+        }
+        let replacing = self.replace.as_deref() == Some(self.name.trim());
+        if ui.button(None, if replacing { "Replace" } else { "Save" }) {
+          match validate_name(&self.name) {
+            Ok(n) if !replacing && Path::new(&loading::stamp_path(&n)).exists() => {
+              self.error = Some(format!("'{}' exists, save again to replace it.", n));
+              self.replace = Some(n);
+            }
+            Ok(n) => name = Some(n),
+            Err(e) => self.error = Some(e),
+          }
+        }
+        ui.same_line(0.0);
+        if ui.button(None, "Cancel") {
+          close = true;
+        }
+      });
+    if name.is_some() || close || is_key_pressed(KeyCode::Escape) {
+      self.open = false;
+    }
+    name
+  }
+
+  /** Whether the screen position is over the dialog. */
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    self.open && root_ui().is_mouse_over(Vec2::new(pos.0, pos.1))
+  }
+}
+
+/** Names become file names, so only letters, digits, - and _ are allowed. */
+fn validate_name(name: &str) -> Result<String, String> {
+  let name = name.trim();
+  if name.is_empty() {
+    return Err("Enter a name.".to_owned());
+  }
+  if !name
+    .chars()
+    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+  {
+    return Err("Use letters, digits, - and _ only.".to_owned());
+  }
+  Ok(name.to_owned())
+}
@@ -1,17 +1,15 @@
-use core::panic;
-
 use macroquad::{
-  prelude::{vec2, Vec2, BLUE, RED, WHITE},
+  prelude::{vec2, Vec2, BLUE, RED},
   shapes::draw_rectangle_lines,
   text::{draw_text_ex, TextParams},
-  texture::{draw_texture_ex, DrawTextureParams},
 };
 
 use crate::{
   assets::Assets,
-  palette::PaletteName,
+  atlas::TileBatch,
+  camera::Camera,
   rect::Rect,
-  sprite::{SpriteId, SpriteKey, SpritePaletteKey},
+  sprite::SpriteKey,
   ui::{FontWeight, Lang},
   world::TILE_SIZE,
 };
@@ -25,35 +23,20 @@ pub struct Tile {
 }
 
 impl Tile {
-  pub fn draw(&mut self, assets: &mut Assets, scroll: (f32, f32), zoom: f32) {
-    let rect = self.draw_rect;
-    let tl = rect.tl();
-    let width = rect.width() as f32 * zoom;
-    let height = rect.height() as f32 * zoom;
-    let tex = match &self.sprite_key {
-      SpriteKey::Specific(sprite_palette) => assets.get_texture(&sprite_palette),
-      SpriteKey::Nine(sprite_nine_palette) => assets.get_nine_slice(sprite_nine_palette),
-      _ => panic!("Group texture not implemented yet."),
-    };
-    draw_texture_ex(
-      *tex,
-      (tl.0 as f32 + scroll.0) * zoom,
-      (tl.1 as f32 + scroll.1) * zoom,
-      WHITE,
-      DrawTextureParams {
-        dest_size: Some(vec2(width, height)),
-        ..Default::default()
-      },
+  /** Queues the tile in `batch`, see `World::draw`. Unfilled tiles let lower layers show through. */
+  pub fn draw(&self, assets: &mut Assets, batch: &mut TileBatch, camera: &Camera, filled: bool) {
+    let (pos, size) = self.screen_rect(camera);
+    let region = assets.get_key_texture(&self.sprite_key, &self.x, &self.y, filled);
+    batch.push(
+      region,
+      macroquad::prelude::Rect::new(pos.x, pos.y, size.x, size.y),
     );
   }
 
-  pub fn debug_draw(&self, assets: &Assets, zoom: f32, scroll_pos: &Vec2) {
-    let width = self.draw_rect.width() as f32 * zoom;
-    let height = self.draw_rect.height() as f32 * zoom;
-    let tl = self.draw_rect.tl();
-    let x = (tl.0 as f32 + scroll_pos.x) * zoom;
-    let y = (tl.1 as f32 + scroll_pos.y) * zoom;
-    draw_rectangle_lines(x, y, width, height, 1.0, BLUE);
+  pub fn debug_draw(&self, assets: &Assets, camera: &Camera) {
+    let (pos, size) = self.screen_rect(camera);
+    let (x, y) = (pos.x, pos.y);
+    draw_rectangle_lines(x, y, size.x, size.y, 1.0, BLUE);
     draw_text_ex(
       format!("{},{}", self.x, self.y).as_str(),
       x + 2.0,
@@ -67,13 +50,20 @@ impl Tile {
     )
   }
 
-  pub fn get_drawing_rect(&self) -> &Rect {
-    &self.draw_rect
+  /** Top left and size on screen. */
+  fn screen_rect(&self, camera: &Camera) -> (Vec2, Vec2) {
+    let tl = self.draw_rect.tl();
+    let pos = camera.world_to_screen(vec2(tl.0 as f32, tl.1 as f32));
+    let size = vec2(
+      self.draw_rect.width() as f32,
+      self.draw_rect.height() as f32,
+    ) * camera.zoom;
+    (pos, size)
   }
 
-  pub fn new(x: usize, y: usize, sid: SpriteId, palette: PaletteName) -> Tile {
+  pub fn from_sprite_key(x: usize, y: usize, sprite_key: SpriteKey) -> Tile {
     Tile {
-      sprite_key: SpriteKey::Specific(SpritePaletteKey::new(sid, palette, palette)),
+      sprite_key,
       x,
       y,
       draw_rect: Rect::new(
@@ -85,6 +75,10 @@ impl Tile {
     }
   }
 
+  /** Moves the tile to another cell, e.g. when the world is resized. */
+  pub fn set_position(&mut self, x: usize, y: usize) {
+    *self = Tile::from_sprite_key(x, y, self.sprite_key.clone());
+  }
   pub fn set_visual(&mut self, sprite_key: SpriteKey) {
     self.sprite_key = sprite_key;
   }
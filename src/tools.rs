@@ -0,0 +1,523 @@
+use std::collections::VecDeque;
+
+use hashbrown::HashSet;
+use macroquad::{
+  hash,
+  prelude::*,
+  ui::{root_ui, widgets},
+};
+use strum::Display;
+
+use crate::{
+  assets::Assets,
+  input::is_alt_down,
+  interaction::{Hit, Selections},
+  math::grid_line,
+  selection::Selection,
+  sprite::SpriteKey,
+  ui::{FontWeight, Lang},
+  world::{World, TILE_SIZE},
+};
+
+pub const MAX_BRUSH_SIZE: usize = 9;
+const PREVIEW_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.3);
+const PREVIEW_OUTLINE_COLOR: Color = Color::new(1.0, 1.0, 0.0, 0.8);
+const STATUS_TEXT_SIZE: u16 = 18;
+/** Fills of more cells than this ask before they are made. */
+const FILL_CONFIRM_CELLS: usize = 5000;
+const CONFIRM_WIDTH: f32 = 260.0;
+const CONFIRM_HEIGHT: f32 = 90.0;
+
+#[derive(Debug, PartialEq, Clone, Copy, Display)]
+pub enum Tool {
+  Brush,
+  Fill,
+  Rectangle,
+  Line,
+  Ellipse,
+  Eyedropper,
+  Select,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Display)]
+pub enum BrushShape {
+  Square,
+  Circle,
+}
+
+/** Which neighbours a fill spreads to. */
+#[derive(Debug, PartialEq, Clone, Copy, Display)]
+pub enum Connectivity {
+  //Left, right, up and down.
+  Four,
+  //Diagonals too.
+  Eight,
+}
+
+impl Connectivity {
+  fn offsets(&self) -> &'static [(isize, isize)] {
+    match self {
+      Connectivity::Four => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+      Connectivity::Eight => &[
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+      ],
+    }
+  }
+}
+
+/** What a tile must share with the clicked one to be filled. Empty cells only match empty cells. */
+#[derive(Debug, PartialEq, Clone, Copy, Display)]
+pub enum FillMatch {
+  Sprite,
+  Palette,
+  Both,
+}
+
+impl FillMatch {
+  fn matches(&self, a: Option<&SpriteKey>, b: Option<&SpriteKey>) -> bool {
+    let (a, b) = match (a, b) {
+      (None, None) => return true,
+      (Some(a), Some(b)) => (a, b),
+      _ => return false,
+    };
+    let same_palettes = a.get_fg() == b.get_fg() && a.get_bg() == b.get_bg();
+    match self {
+      FillMatch::Sprite => a.same_sprite(b),
+      FillMatch::Palette => same_palettes,
+      FillMatch::Both => a.same_sprite(b) && same_palettes,
+    }
+  }
+  fn next(&self) -> FillMatch {
+    match self {
+      FillMatch::Sprite => FillMatch::Palette,
+      FillMatch::Palette => FillMatch::Both,
+      FillMatch::Both => FillMatch::Sprite,
+    }
+  }
+}
+
+/** The tool that left clicks in the world use, and its settings. */
+pub struct Tools {
+  pub tool: Tool,
+  //1 to `MAX_BRUSH_SIZE` cells wide.
+  pub brush_size: usize,
+  pub brush_shape: BrushShape,
+  pub fill_connectivity: Connectivity,
+  pub fill_match: FillMatch,
+  //Cell under the cursor last frame while painting, `None` when the button is up.
+  last_cell: Option<(isize, isize)>,
+  //A fill larger than `FILL_CONFIRM_CELLS` waiting for confirmation.
+  pending_fill: Option<Vec<(usize, usize)>>,
+  //Rectangles and ellipses, lines are always one cell wide.
+  pub shape_filled: bool,
+  //Corners of the shape being dragged, `None` when not dragging.
+  shape_start: Option<(usize, usize)>,
+  shape_end: (usize, usize),
+  pub selection: Selection,
+}
+
+impl Default for Tools {
+  fn default() -> Tools {
+    Tools {
+      tool: Tool::Brush,
+      brush_size: 1,
+      brush_shape: BrushShape::Square,
+      fill_connectivity: Connectivity::Four,
+      fill_match: FillMatch::Both,
+      last_cell: None,
+      pending_fill: None,
+      shape_filled: false,
+      shape_start: None,
+      shape_end: (0, 0),
+      selection: Selection::default(),
+    }
+  }
+}
+
+impl Tools {
+  /**
+  B picks the brush, [ and ] change its size and Q switches between square and circle.
+  F picks the fill, Q switches between 4 and 8 neighbours and E changes what has to match.
+  R, L and O pick the rectangle, line and ellipse, Q switches between outlined and filled.
+  I picks the eyedropper and M the selection, see `Selection::update_keys` for the keys of a
+  floating block.
+  */
+  pub fn update_keys(&mut self) {
+    if is_key_pressed(KeyCode::B) {
+      self.tool = Tool::Brush;
+    }
+    if is_key_pressed(KeyCode::F) {
+      self.tool = Tool::Fill;
+    }
+    for (key, tool) in [
+      (KeyCode::R, Tool::Rectangle),
+      (KeyCode::L, Tool::Line),
+      (KeyCode::O, Tool::Ellipse),
+      (KeyCode::I, Tool::Eyedropper),
+      (KeyCode::M, Tool::Select),
+    ] {
+      if is_key_pressed(key) {
+        self.tool = tool;
+        self.shape_start = None;
+      }
+    }
+    match self.tool {
+      Tool::Brush => {
+        if is_key_pressed(KeyCode::LeftBracket) {
+          self.brush_size = (self.brush_size - 1).max(1);
+        }
+        if is_key_pressed(KeyCode::RightBracket) {
+          self.brush_size = (self.brush_size + 1).min(MAX_BRUSH_SIZE);
+        }
+        if is_key_pressed(KeyCode::Q) {
+          self.brush_shape = match self.brush_shape {
+            BrushShape::Square => BrushShape::Circle,
+            BrushShape::Circle => BrushShape::Square,
+          };
+        }
+      }
+      Tool::Fill => {
+        if is_key_pressed(KeyCode::Q) {
+          self.fill_connectivity = match self.fill_connectivity {
+            Connectivity::Four => Connectivity::Eight,
+            Connectivity::Eight => Connectivity::Four,
+          };
+        }
+        if is_key_pressed(KeyCode::E) {
+          self.fill_match = self.fill_match.next();
+        }
+      }
+      Tool::Rectangle | Tool::Ellipse => {
+        if is_key_pressed(KeyCode::Q) {
+          self.shape_filled = !self.shape_filled;
+        }
+      }
+      Tool::Line | Tool::Eyedropper | Tool::Select => {}
+    }
+    self.selection.update_keys();
+  }
+
+  /** Label for the history entry of an edit made with the current tool. */
+  pub fn action_label(&self, selections: &Selections) -> &'static str {
+    if self.selection.is_floating() {
+      return self.selection.action_label();
+    }
+    match self.tool {
+      Tool::Brush => selections.action_label(),
+      Tool::Fill => "Fill",
+      Tool::Rectangle => "Rectangle",
+      Tool::Line => "Line",
+      Tool::Ellipse => "Ellipse",
+      Tool::Eyedropper => "Eyedropper",
+      Tool::Select => "Move",
+    }
+  }
+  /** While a huge fill waits for confirmation, the world takes no clicks. */
+  pub fn is_confirming(&self) -> bool {
+    self.pending_fill.is_some()
+  }
+
+  /**
+  Cells to edit this frame, only presses that are not over the UI start an edit.
+  The brush paints while the button is held, filling in the cells between the last and the
+  current mouse position so fast drags do not leave gaps. The fill reacts to the press and
+  shapes are made when the button is released. Alt+click picks a tile with any tool.
+  A pasted or moved block takes the clicks until it is placed.
+  */
+  pub fn update(&mut self, world: &World, mouse_over_ui: bool) -> Option<Hit> {
+    let pressed = is_mouse_button_pressed(MouseButton::Left) && !mouse_over_ui;
+    if self.tool == Tool::Select || self.selection.is_floating() {
+      return self.selection.update(world, pressed);
+    }
+    //A shape being dragged is finished first.
+    if self.tool == Tool::Eyedropper || (is_alt_down() && self.shape_start.is_none()) {
+      return world
+        .mouse_grid
+        .filter(|_| pressed)
+        .map(|(x, y)| Hit::Pick(x, y));
+    }
+    match self.tool {
+      Tool::Brush => self.update_brush(world, pressed),
+      Tool::Fill => self.update_fill(world, pressed),
+      Tool::Rectangle | Tool::Line | Tool::Ellipse => self.update_shape(world, pressed),
+      Tool::Eyedropper | Tool::Select => None,
+    }
+  }
+  fn update_brush(&mut self, world: &World, pressed: bool) -> Option<Hit> {
+    let cell = world.screen_to_cell(&world.mouse_pos);
+    if pressed {
+      self.last_cell = Some(cell);
+      return Some(Hit::Tiles(self.brush_cells(world, &[cell])));
+    }
+    if !is_mouse_button_down(MouseButton::Left) {
+      self.last_cell = None;
+    }
+    let last = self.last_cell.filter(|last| *last != cell)?;
+    self.last_cell = Some(cell);
+    Some(Hit::Tiles(
+      self.brush_cells(world, &grid_line(last, cell)[1..]),
+    ))
+  }
+  fn update_fill(&mut self, world: &World, pressed: bool) -> Option<Hit> {
+    //No confirmation for a fill that would not change anything.
+    if !pressed || !world.is_active_layer_editable() {
+      return None;
+    }
+    let start = world.cell_in_world(world.screen_to_cell(&world.mouse_pos))?;
+    let region = self.fill_region(world, start);
+    if region.len() > FILL_CONFIRM_CELLS {
+      self.pending_fill = Some(region);
+      return None;
+    }
+    Some(Hit::Tiles(region))
+  }
+  /** Escape drops the shape being dragged. */
+  fn update_shape(&mut self, world: &World, pressed: bool) -> Option<Hit> {
+    if pressed {
+      self.shape_start = world.px_to_grid(None);
+    }
+    if is_key_pressed(KeyCode::Escape) {
+      self.shape_start = None;
+    }
+    let start = self.shape_start?;
+    //Outside the world the shape keeps the last end inside it.
+    if let Some(end) = world.px_to_grid(None) {
+      self.shape_end = end;
+    }
+    if pressed {
+      self.shape_end = start;
+    }
+    if !is_mouse_button_released(MouseButton::Left) {
+      return None;
+    }
+    self.shape_start = None;
+    Some(Hit::Tiles(self.shape_cells(start, self.shape_end)))
+  }
+
+  /** Cells of the current shape tool dragged from `start` to `end`, both corners included. */
+  fn shape_cells(&self, start: (usize, usize), end: (usize, usize)) -> Vec<(usize, usize)> {
+    let (left, right) = (start.0.min(end.0), start.0.max(end.0));
+    let (top, bottom) = (start.1.min(end.1), start.1.max(end.1));
+    let in_box = (top..=bottom).flat_map(|y| (left..=right).map(move |x| (x, y)));
+    match self.tool {
+      Tool::Line => grid_line(
+        (start.0 as isize, start.1 as isize),
+        (end.0 as isize, end.1 as isize),
+      )
+      .into_iter()
+      .map(|(x, y)| (x as usize, y as usize))
+      .collect(),
+      Tool::Rectangle => in_box
+        .filter(|(x, y)| {
+          self.shape_filled || *x == left || *x == right || *y == top || *y == bottom
+        })
+        .collect(),
+      Tool::Ellipse => {
+        //Cells whose center is inside the ellipse touching the edges of the box.
+        let center = vec2((left + right) as f32, (top + bottom) as f32) / 2.0;
+        let radius = vec2((right - left) as f32, (bottom - top) as f32) / 2.0 + 0.5;
+        let inside = |x: isize, y: isize| {
+          let d = (vec2(x as f32, y as f32) - center) / radius;
+          d.length_squared() <= 1.0
+        };
+        in_box
+          .filter(|(x, y)| {
+            let (x, y) = (*x as isize, *y as isize);
+            //The outline is every inside cell next to an outside one.
+            inside(x, y)
+              && (self.shape_filled
+                || !(inside(x - 1, y) && inside(x + 1, y) && inside(x, y - 1) && inside(x, y + 1)))
+          })
+          .collect()
+      }
+      Tool::Brush | Tool::Fill | Tool::Eyedropper | Tool::Select => vec![],
+    }
+  }
+
+  /** Offsets from the cell under the cursor of every cell the brush covers. */
+  fn brush_offsets(&self) -> Vec<(isize, isize)> {
+    let size = self.brush_size as isize;
+    //Even sizes have one more cell to the right and below the cursor.
+    let start = -(size - 1) / 2;
+    let center = start as f32 + size as f32 / 2.0;
+    //A bit under half the size, otherwise a circle of 3 is a square.
+    let radius = size as f32 / 2.0 - 0.2;
+    let mut offsets = Vec::with_capacity(self.brush_size * self.brush_size);
+    for dy in start..start + size {
+      for dx in start..start + size {
+        let inside = match self.brush_shape {
+          BrushShape::Square => true,
+          BrushShape::Circle => {
+            vec2(dx as f32 + 0.5 - center, dy as f32 + 0.5 - center).length() <= radius
+          }
+        };
+        if inside {
+          offsets.push((dx, dy));
+        }
+      }
+    }
+    offsets
+  }
+  /** Cells the brush covers at every center, each once and only inside the world. */
+  fn brush_cells(&self, world: &World, centers: &[(isize, isize)]) -> Vec<(usize, usize)> {
+    let offsets = self.brush_offsets();
+    let mut seen = HashSet::new();
+    centers
+      .iter()
+      .flat_map(|(x, y)| offsets.iter().map(move |(dx, dy)| (x + dx, y + dy)))
+      .filter_map(|cell| world.cell_in_world(cell))
+      .filter(|cell| seen.insert(*cell))
+      .collect()
+  }
+
+  /** Cells connected to `start` with a tile that matches the one at `start`, see `FillMatch`. */
+  fn fill_region(&self, world: &World, start: (usize, usize)) -> Vec<(usize, usize)> {
+    let key_at = |(x, y): (usize, usize)| world.get_tile(&x, &y).map(|t| t.get_sprite_key());
+    let target = key_at(start);
+    let mut region = vec![];
+    let mut seen = HashSet::new();
+    seen.insert(start);
+    let mut queue = VecDeque::from([start]);
+    while let Some((x, y)) = queue.pop_front() {
+      region.push((x, y));
+      for (dx, dy) in self.fill_connectivity.offsets() {
+        let Some(next) = world.cell_in_world((x as isize + dx, y as isize + dy)) else {
+          continue;
+        };
+        if !seen.contains(&next) && self.fill_match.matches(target, key_at(next)) {
+          seen.insert(next);
+          queue.push_back(next);
+        }
+      }
+    }
+    region
+  }
+
+  /** Asks before a fill of more than `FILL_CONFIRM_CELLS` cells. Returns it once confirmed. */
+  pub fn draw_fill_confirmation(&mut self) -> Option<Hit> {
+    let cells = self.pending_fill.as_ref()?.len();
+    let mut confirmed = false;
+    let mut cancelled = false;
+    let pos = vec2(
+      (screen_width() - CONFIRM_WIDTH) / 2.0,
+      (screen_height() - CONFIRM_HEIGHT) / 2.0,
+    );
+    widgets::Window::new(hash!(), pos, vec2(CONFIRM_WIDTH, CONFIRM_HEIGHT))
+      .label("Fill")
+      .movable(false)
+      .ui(&mut root_ui(), |ui| {
+        ui.label(None, &format!("Fill {} tiles?", cells));
+        confirmed = ui.button(None, "Fill");
+        ui.same_line(0.0);
+        cancelled = ui.button(None, "Cancel") || is_key_pressed(KeyCode::Escape);
+      });
+    if confirmed {
+      return self.pending_fill.take().map(Hit::Tiles);
+    }
+    if cancelled {
+      self.pending_fill = None;
+    }
+    None
+  }
+  /** Whether the screen position is over the fill confirmation. */
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    self.is_confirming() && root_ui().is_mouse_over(vec2(pos.0, pos.1))
+  }
+
+  /** Highlights the cells a click would change, or the shape being dragged. */
+  pub fn draw_preview(&self, world: &World, mouse_over_ui: bool) {
+    self.selection.draw_marquee(world);
+    if self.selection.is_floating() {
+      return;
+    }
+    if let Some(start) = self.shape_start {
+      for (x, y) in self.shape_cells(start, self.shape_end) {
+        draw_cell(world, x, y, PREVIEW_COLOR);
+      }
+      return;
+    }
+    if mouse_over_ui {
+      return;
+    }
+    let cell = world.screen_to_cell(&world.mouse_pos);
+    let cells = match self.tool {
+      Tool::Brush if !is_alt_down() => self.brush_cells(world, &[cell]),
+      //The region is only searched on click, huge regions are slow to find every frame.
+      _ => world.cell_in_world(cell).into_iter().collect(),
+    };
+    for (x, y) in cells {
+      draw_cell(world, x, y, PREVIEW_COLOR);
+    }
+  }
+
+  /** Current tool and its keys at the bottom of the screen. */
+  pub fn draw_status(&self, assets: &Assets) {
+    let text = match self.tool {
+      _ if self.selection.is_floating() => format!(
+        "{}   click to place   H V flip   T turn   Esc cancel",
+        self.selection.action_label()
+      ),
+      Tool::Brush => format!(
+        "{} {} {}   [ ] size   Q shape",
+        self.tool,
+        self.brush_size,
+        self.brush_shape.to_string().to_lowercase()
+      ),
+      Tool::Fill => format!(
+        "{} {} neighbours, match {}   Q neighbours   E match",
+        self.tool,
+        match self.fill_connectivity {
+          Connectivity::Four => 4,
+          Connectivity::Eight => 8,
+        },
+        self.fill_match.to_string().to_lowercase()
+      ),
+      Tool::Rectangle | Tool::Ellipse => format!(
+        "{} {}   drag to draw   Q fill",
+        self.tool,
+        if self.shape_filled {
+          "filled"
+        } else {
+          "outlined"
+        }
+      ),
+      Tool::Line => format!("{}   drag to draw", self.tool),
+      Tool::Eyedropper => format!(
+        "{}   click a tile to pick its sprite and colours",
+        self.tool
+      ),
+      Tool::Select => format!(
+        "{}   drag to select, drag inside to move   Ctrl+C copy   Ctrl+X cut   Ctrl+V paste   Ctrl+Shift+S stamp",
+        self.tool
+      ),
+    };
+    draw_text_ex(
+      &text,
+      screen_width() / 2.0 - 120.0,
+      screen_height() - 10.0,
+      TextParams {
+        font: assets.fonts.get_font(&Lang::EnUs, &FontWeight::Regular),
+        font_size: STATUS_TEXT_SIZE,
+        color: BLACK,
+        ..Default::default()
+      },
+    );
+  }
+}
+
+/** Fills a grid cell with `color` and outlines it. */
+fn draw_cell(world: &World, x: usize, y: usize, color: Color) {
+  let pos = world
+    .camera
+    .world_to_screen(vec2((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32));
+  let size = TILE_SIZE as f32 * world.camera.zoom;
+  draw_rectangle(pos.x, pos.y, size, size, color);
+  draw_rectangle_lines(pos.x, pos.y, size, size, 1.0, PREVIEW_OUTLINE_COLOR);
+}
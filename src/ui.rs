@@ -1,19 +1,45 @@
 use enum_map::{Enum, EnumMap};
-use macroquad::{text::Font, window::screen_width};
+use macroquad::{
+  prelude::{is_mouse_button_pressed, mouse_position, MouseButton},
+  text::Font,
+  window::{screen_height, screen_width},
+};
 use strum::Display;
 
 use crate::{
   assets::Assets,
-  button::{Button, ButtonKind},
+  button::{Button, ButtonKind, ButtonLabel},
   debug::DebugState,
-  interaction::Hit,
+  interaction::Selections,
   palette::PaletteName,
+  picker::Picker,
   rect::Rect,
+  world::World,
 };
 
+const PANEL_GAP: usize = 10;
+const UI_PALETTES: [PaletteName; 5] = [
+  PaletteName::Red,
+  PaletteName::Green,
+  PaletteName::Blue,
+  PaletteName::Brown,
+  PaletteName::Yellow,
+];
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum UIHit {
   Button(ButtonKind),
+  //Index into the stamp panel, see `StampPanel::draw`.
+  Stamp(usize),
+  //A click on a panel that did not hit a button, it should not reach the world.
+  Panel,
+}
+
+pub struct UiLayout {
+  buttons: Vec<Button>,
+  picker: Picker,
+  //Room below the layer panel for the `StampPanel`.
+  stamp_area: Rect,
 }
 
 #[derive(Debug, Enum, Clone, Copy)]
@@ -42,56 +68,171 @@ impl Fonts {
   }
 }
 
-pub fn create_ui_layout(assets: &Assets) -> Vec<Button> {
+/**
+Rebuild it when the world is replaced, the layer panel depends on its layers, or when a stamp
+is saved.
+*/
+pub fn create_ui_layout(assets: &Assets, world: &World) -> UiLayout {
   let palette_margin = Rect::new(0, 2, 10, 2);
   let palette_width = assets.sprites.palette.width();
   let palette_padding = Rect::new(palette_width / 5, 0, 0, 0);
   let palettes_left = screen_width() as usize - palette_width - palette_margin.right;
-  let mut top = 0 as usize;
+  let mut top = 0;
   let mut btns = vec![];
-  for pn in vec![
-    PaletteName::Red,
-    PaletteName::Green,
-    PaletteName::Blue,
-    PaletteName::Brown,
-    PaletteName::Yellow,
-  ] {
+  for pn in UI_PALETTES {
     let btn = Button::new(
       &assets.fonts,
-      ButtonKind::Palette(pn.clone()),
+      ButtonKind::Palette(pn),
       palettes_left,
       top,
       palette_width,
       assets.sprites.palette.height(),
-      Some(palette_margin.clone()),
-      Some(palette_padding.clone()),
+      Some(palette_margin),
+      Some(palette_padding),
       None,
     );
     top += btn.bounds().height();
     btns.push(btn);
   }
 
-  btns
+  //Narrower strip for the background to the left of the foreground palettes.
+  let bg_width = palette_width / 2;
+  let bg_left = palettes_left - bg_width - palette_margin.right;
+  let mut bg_top = 0;
+  let bg_kinds = std::iter::once(ButtonKind::AutoBackground)
+    .chain(UI_PALETTES.into_iter().map(ButtonKind::BackgroundPalette));
+  for kind in bg_kinds {
+    let label = match kind {
+      ButtonKind::AutoBackground => Some(ButtonLabel {
+        text: "auto".to_owned(),
+        color: None,
+        size: Some(16),
+        weight: None,
+      }),
+      _ => None,
+    };
+    let btn = Button::new(
+      &assets.fonts,
+      kind,
+      bg_left,
+      bg_top,
+      bg_width,
+      assets.sprites.palette.height() / 2,
+      Some(palette_margin),
+      Some(Rect::new(bg_width / 5, 0, 0, 0)),
+      label,
+    );
+    bg_top += btn.bounds().height();
+    btns.push(btn);
+  }
+
+  //Layer rows left of the background strip, the top layer first.
+  let layer_height = assets.sprites.palette.height() / 2;
+  let name_width = palette_width * 3 / 4;
+  let toggle_width = palette_width / 4;
+  let layers_left = bg_left - name_width - toggle_width * 2 - palette_margin.right * 3;
+  let mut layer_top = 0;
+  for (i, layer) in world.layers().iter().enumerate().rev() {
+    let mut left = layers_left;
+    let mut row_height = 0;
+    for (kind, text, width) in [
+      (ButtonKind::Layer(i), layer.name.clone(), name_width),
+      (ButtonKind::LayerVisible(i), "show".to_owned(), toggle_width),
+      (ButtonKind::LayerLocked(i), "lock".to_owned(), toggle_width),
+    ] {
+      let btn = Button::new(
+        &assets.fonts,
+        kind,
+        left,
+        layer_top,
+        width,
+        layer_height,
+        Some(palette_margin),
+        Some(Rect::new(4, 0, 0, 0)),
+        Some(ButtonLabel {
+          text,
+          color: None,
+          size: Some(14),
+          weight: None,
+        }),
+      );
+      left = btn.bounds().right;
+      row_height = btn.bounds().height();
+      btns.push(btn);
+    }
+    layer_top += row_height;
+  }
+
+  let picker = Picker::new(
+    assets,
+    Rect::new(
+      palettes_left,
+      top + PANEL_GAP,
+      palettes_left + palette_width,
+      screen_height() as usize - PANEL_GAP,
+    ),
+  );
+
+  let stamp_area = Rect::new(
+    layers_left,
+    layer_top + PANEL_GAP,
+    bg_left - palette_margin.right,
+    screen_height() as usize - PANEL_GAP,
+  );
+
+  UiLayout {
+    buttons: btns,
+    picker,
+    stamp_area,
+  }
+}
+
+impl UiLayout {
+  /** Whether the screen position is over a button or a panel. */
+  pub fn contains(&self, pos: &(f32, f32)) -> bool {
+    self.picker.area().contains(pos) || self.buttons.iter().any(|b| b.bounds().contains(pos))
+  }
+  pub fn stamp_area(&self) -> &Rect {
+    &self.stamp_area
+  }
 }
 
 pub fn draw_ui(
   deb: &DebugState,
-  assets: &Assets,
-  ui: &Vec<Button>,
-  selected_palette: &PaletteName,
+  assets: &mut Assets,
+  ui: &mut UiLayout,
+  selections: &Selections,
+  world: &World,
 ) -> Option<UIHit> {
   let mut hit = None;
-  ui.iter().for_each(|b| {
+  ui.buttons.iter().for_each(|b| {
     let selected = match b.kind() {
-      ButtonKind::Palette(pn) => pn == selected_palette,
-      _ => false,
+      ButtonKind::Layer(i) => world.active_layer() == *i,
+      ButtonKind::LayerVisible(i) => world.layers()[*i].visible,
+      ButtonKind::LayerLocked(i) => world.layers()[*i].locked,
+      kind => selections.is_selected(kind),
     };
-    let state = b.draw(deb, assets, selected);
+    let state = b.draw(
+      deb,
+      assets,
+      selected,
+      (selections.foreground(), selections.background()),
+    );
     if state.hit {
       println!("btn hit:{:?}", b.kind());
-      hit = Some(UIHit::Button(b.kind().clone()));
+      hit = Some(UIHit::Button(*b.kind()));
     }
   });
+  if let Some(kind) = ui.picker.draw(deb, assets, selections) {
+    hit = Some(UIHit::Button(kind));
+  }
+
+  if hit.is_none()
+    && is_mouse_button_pressed(MouseButton::Left)
+    && ui.picker.area().contains(&mouse_position())
+  {
+    hit = Some(UIHit::Panel);
+  }
 
   hit
 }
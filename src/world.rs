@@ -1,173 +1,697 @@
-use enum_map::Enum;
+use hashbrown::HashSet;
 use macroquad::{prelude::*, texture::Image};
-use std::str::FromStr;
-use strum::EnumIter;
+use std::ops::Range;
 
 use crate::{
   assets::Assets,
+  atlas::TileBatch,
+  autotile::BlobMask,
+  camera::Camera,
   debug::DebugState,
   math::{i_to_xy, xy_to_i},
   palette::PaletteName,
-  sprite::{clr_to_sid, SpriteClrId, SpriteId},
+  registry::{registry, SpriteDef, GROUP_ALPHA, NINE_ALPHA},
+  sprite::{
+    SpriteGroup, SpriteGroupPaletteKey, SpriteId, SpriteKey, SpriteNineGroup, SpriteNinePaletteKey,
+    SpritePaletteKey,
+  },
   tile::Tile,
 };
 
 pub const TILE_SIZE: usize = 16;
-const BASE_MOVEMENT_SPEED: f32 = 500.0;
+/** Largest width or height of a map in tiles, layer images and the minimap use 16 bit sizes. */
+pub const MAX_MAP_SIZE: usize = 1024;
+//Transparent and outside the bands, so it imports as an empty cell.
+const EMPTY_CELL_PIXEL: [u8; 4] = [255, 0, 255, 0];
 
-#[derive(Debug)]
+/** What a world.png pixel holds. Groups own the alpha band from their colour code in the manifest. */
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum WorldPixelAlpha {
-  //150-199 SpriteGroup
-  Trees = 150,
-  Mounds = 160,
-  Decorations = 170,
-  //200-250 SpriteNineGroup
-  NineTrees = 200,
-  NineMounds = 230,
-  NineWater = 250,
-  //Specific sprite
-  Sprite = 255,
+  //150-199
+  Group(SpriteGroup),
+  //200-254
+  Nine(SpriteNineGroup),
+  //255
+  Sprite,
 }
 
-pub struct World {
-  _scroll_pos: Vec2,
-  prev_mouse_pos: (f32, f32),
+/** What changed since the last `World::take_changes`. */
+#[derive(Debug, PartialEq)]
+pub enum WorldChanges {
+  //A new world, redraw everything.
+  All,
+  Cells(Vec<(usize, usize)>),
+}
+
+/** Which part of the map stays in place when it is resized. */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Anchor {
+  TopLeft,
+  Top,
+  TopRight,
+  Left,
+  Center,
+  Right,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+impl Anchor {
+  /** Anchor opposite the sides that change, -1 for left/up, 1 for right/down and 0 for neither. */
+  pub fn opposite(dx: isize, dy: isize) -> Anchor {
+    match (dx.signum(), dy.signum()) {
+      (-1, -1) => Anchor::BottomRight,
+      (0, -1) => Anchor::Bottom,
+      (1, -1) => Anchor::BottomLeft,
+      (-1, 0) => Anchor::Right,
+      (0, 0) => Anchor::Center,
+      (1, 0) => Anchor::Left,
+      (-1, 1) => Anchor::TopRight,
+      (0, 1) => Anchor::Top,
+      _ => Anchor::TopLeft,
+    }
+  }
+  //Halves of the size change that go to the left and top side: 0, 1 or 2.
+  fn halves(&self) -> (isize, isize) {
+    match self {
+      Anchor::TopLeft => (0, 0),
+      Anchor::Top => (1, 0),
+      Anchor::TopRight => (2, 0),
+      Anchor::Left => (0, 1),
+      Anchor::Center => (1, 1),
+      Anchor::Right => (2, 1),
+      Anchor::BottomLeft => (0, 2),
+      Anchor::Bottom => (1, 2),
+      Anchor::BottomRight => (2, 2),
+    }
+  }
+  /** Where the old top left cell ends up in the resized map, negative when it is cut off. */
+  fn offset(&self, old: (usize, usize), new: (usize, usize)) -> (isize, isize) {
+    let (hx, hy) = self.halves();
+    (
+      (new.0 as isize - old.0 as isize) * hx / 2,
+      (new.1 as isize - old.1 as isize) * hy / 2,
+    )
+  }
+}
+
+/** Layer names of a new world, bottom to top. world.png holds the first one. */
+pub const DEFAULT_LAYERS: [&str; 3] = ["ground", "decoration", "overlay"];
+
+/** Cells of a world at one point in time, for history entries that change its size. */
+#[derive(Debug, Clone)]
+pub struct WorldSnapshot {
+  width: usize,
+  height: usize,
+  //Per layer, row by row.
+  layers: Vec<Vec<Option<SpriteKey>>>,
+}
+impl WorldSnapshot {
+  pub fn width(&self) -> usize {
+    self.width
+  }
+  pub fn height(&self) -> usize {
+    self.height
+  }
+}
+
+/** One tile per cell. Nine-slices only connect to tiles on their own layer. */
+pub struct Layer {
+  pub name: String,
+  pub visible: bool,
+  //Locked layers are drawn but not edited.
+  pub locked: bool,
   tiles: Vec<Option<Tile>>,
+}
+
+impl Layer {
+  pub fn new(name: &str, tiles: Vec<Option<Tile>>) -> Layer {
+    Layer {
+      name: name.to_owned(),
+      visible: true,
+      locked: false,
+      tiles,
+    }
+  }
+  pub fn empty(name: &str, cells: usize) -> Layer {
+    Layer::new(name, vec![None; cells])
+  }
+}
 
+pub struct World {
+  //Bottom to top, never empty.
+  layers: Vec<Layer>,
+  //Layer that edits go to.
+  active_layer: usize,
+  changes: WorldChanges,
+
+  pub camera: Camera,
+
+  //Tiles drawn last frame over all visible layers, see `visible_grid`.
+  pub drawn_tiles: usize,
   pub height: usize,
   pub mouse_grid: Option<(usize, usize)>,
   //Screen position, not world position.
   pub mouse_pos: (f32, f32),
   pub mouse_world_px: Option<(usize, usize)>,
-  pub scroll_pos: Vec2,
   pub width: usize,
-  pub zoom: f32,
 }
 
-impl World {
-  pub fn new(img: Image) -> World {
-    let tiles: Vec<Option<Tile>> = img
-      .get_image_data()
-      .iter()
-      .enumerate()
-      .map(|(i, pixel)| {
-        let (x, y) = i_to_xy(&img.width(), &i);
-
-        if pixel[3] != 255 {
-          //This is a non-standard tile.
-          if let Ok(clr_id) = SpriteClrId::from_str(
-            format!(
-              "C{:0>3}_{:0>3}_{:0>3}_{:0>3}",
-              pixel[0], pixel[1], pixel[2], pixel[3]
-            )
-            .as_str(),
-          ) {
-            // println!("Thing {:?}", clr_id);
-            return Some(Tile::new(x, y, clr_to_sid(clr_id), PaletteName::White));
-            // return None;
-          } else {
-            return None;
-          }
+impl WorldPixelAlpha {
+  pub fn from_alpha(alpha: &u8) -> Option<WorldPixelAlpha> {
+    if *alpha == 255 {
+      Some(WorldPixelAlpha::Sprite)
+    } else if GROUP_ALPHA.contains(alpha) {
+      let i = registry().group_by_alpha(alpha)?;
+      SpriteGroup::from_index(i).map(WorldPixelAlpha::Group)
+    } else if NINE_ALPHA.contains(alpha) {
+      let i = registry().nine_by_alpha(alpha)?;
+      SpriteNineGroup::from_index(i).map(WorldPixelAlpha::Nine)
+    } else {
+      None
+    }
+  }
+  /** Alpha a key is encoded with, `None` when its group has no colour code. */
+  fn from_key(key: &SpriteKey) -> Option<u8> {
+    match key {
+      SpriteKey::Specific(_) => Some(255),
+      SpriteKey::Group(g) => g.sg.def().alpha(),
+      SpriteKey::Nine(n) => n.sg.def().alpha(),
+    }
+  }
+}
+
+/**
+Decodes one world.png pixel. Alpha picks the kind of sprite (see `WorldPixelAlpha`),
+red is the sprite/group index, green the foreground and blue the background palette.
+For sprite groups red 0 means "pick a variant from the position" and n means variant n-1.
+*/
+fn decode_pixel(pixel: &[u8; 4]) -> Option<SpriteKey> {
+  if let Some(sid) = registry()
+    .sprite_by_pixel(pixel)
+    .and_then(SpriteId::from_index)
+  {
+    let def = sid.def();
+    return Some(SpriteKey::Specific(SpritePaletteKey::new(
+      sid,
+      def.foreground,
+      def.background,
+    )));
+  }
+
+  let [index, fg, bg, alpha] = *pixel;
+  let palettes = |def: &SpriteDef| {
+    let foreground = PaletteName::try_from(fg).unwrap_or(def.foreground);
+    let background = PaletteName::try_from(bg).unwrap_or(def.background);
+    (foreground, background)
+  };
+
+  let key = match WorldPixelAlpha::from_alpha(&alpha)? {
+    WorldPixelAlpha::Group(sg) => {
+      let (foreground, background) = palettes(sg.def());
+      SpriteKey::Group(SpriteGroupPaletteKey::new(
+        foreground,
+        background,
+        sg,
+        index.checked_sub(1),
+      ))
+    }
+    //The blob mask is resolved from the neighbours once the whole image is read.
+    WorldPixelAlpha::Nine(sg) => {
+      let (foreground, background) = palettes(sg.def());
+      SpriteKey::Nine(SpriteNinePaletteKey::new(
+        foreground,
+        background,
+        sg,
+        index,
+        BlobMask::ISOLATED,
+      ))
+    }
+    WorldPixelAlpha::Sprite => {
+      let sid = SpriteId::from_index(index as usize).unwrap_or(SpriteId::MISSING);
+      let (foreground, background) = palettes(sid.def());
+      SpriteKey::Specific(SpritePaletteKey::new(sid, foreground, background))
+    }
+  };
+  Some(key)
+}
+
+/** Inverse of `decode_pixel`. */
+fn encode_pixel(key: Option<&SpriteKey>) -> [u8; 4] {
+  let Some(key) = key else {
+    return EMPTY_CELL_PIXEL;
+  };
+  //Registry validation makes every group have a colour code.
+  let alpha = WorldPixelAlpha::from_key(key).unwrap_or(0);
+  match key {
+    SpriteKey::Specific(s) => {
+      //Sprites with a colour code and default palettes keep their original colour code.
+      let def = s.sid.def();
+      if let Some(pixel) = def.pixel() {
+        if s.foreground == def.foreground && s.background == def.background {
+          return pixel;
         }
+      }
+      [
+        s.sid.index() as u8,
+        s.foreground as u8,
+        s.background as u8,
+        alpha,
+      ]
+    }
+    SpriteKey::Group(g) => [
+      g.sg_index.map(|i| i.saturating_add(1)).unwrap_or(0),
+      g.foreground as u8,
+      g.background as u8,
+      alpha,
+    ],
+    SpriteKey::Nine(n) => [n.sg_index, n.foreground as u8, n.background as u8, alpha],
+  }
+}
+
+/** Decodes a colour coded image, see `decode_pixel`. Nine-slices still need resolving. */
+fn decode_image(img: &Image) -> Vec<Option<Tile>> {
+  img
+    .get_image_data()
+    .iter()
+    .enumerate()
+    .map(|(i, pixel)| {
+      let (x, y) = i_to_xy(&img.width(), &i);
+      decode_pixel(pixel).map(|key| Tile::from_sprite_key(x, y, key))
+    })
+    .collect()
+}
 
-        // let sids: Vec<SpriteId> = pixel
-        //   .iter()
-        //   .enumerate()
-        //   .map(|(channel_index, channel_value)| {
-        //     if channel_index > 2 || channel_value < &1 {
-        //       return Err("Not a sprite.");
-        //     }
-        //     let clr_id_str: String = match channel_index {
-        //       0 => format!("C{:0>3}_000_000_255", channel_value),
-        //       1 => format!("C000_{:0>3}_000_255", channel_value),
-        //       2 => format!("C000_000_{:0>3}_255", channel_value),
-        //       _ => format!("C000_000_000_000"),
-        //     };
-        //     if let Ok(clr_id) = SpriteClrId::from_str(&clr_id_str) {
-        //       Ok(clr_to_sid(clr_id))
-        //     } else {
-        //       Err("Not a valid SpriteClrId.")
-        //     }
-        //   })
-        //   .filter(|res| res.is_ok())
-        //   .map(|res| res.unwrap())
-        //   .collect();
-
-        // if sids.len() > 0 {
-        // } else {
-        //   None
-        // }
-        Some(Tile::new(x, y, SpriteId::Missing, PaletteName::White))
+impl World {
+  /** A world with `img` as its ground layer and the other `DEFAULT_LAYERS` empty. */
+  pub fn new(img: Image) -> World {
+    World::from_ground(img.width(), img.height(), decode_image(&img))
+  }
+  /** A world with every ground cell set to `fill`, e.g. from the new map dialog. */
+  pub fn blank(width: usize, height: usize, fill: &SpriteKey) -> World {
+    let ground = (0..width * height)
+      .map(|i| {
+        let (x, y) = i_to_xy(&width, &i);
+        Some(Tile::from_sprite_key(x, y, fill.clone()))
       })
       .collect();
+    World::from_ground(width, height, ground)
+  }
+  fn from_ground(width: usize, height: usize, ground: Vec<Option<Tile>>) -> World {
+    let mut layers = vec![Layer::new(DEFAULT_LAYERS[0], ground)];
+    layers.extend(
+      DEFAULT_LAYERS[1..]
+        .iter()
+        .map(|name| Layer::empty(name, width * height)),
+    );
 
+    let mut world = World::from_layers(width, height, layers, Camera::new(vec2(0.0, 0.0), 2.0));
+    world.resolve_nine_slices();
+    world
+  }
+  /** Replaces the tiles of a layer with a colour coded image of the same size as the world. */
+  pub fn load_layer_image(&mut self, layer: usize, img: &Image) -> Result<(), String> {
+    if img.width() != self.width || img.height() != self.height {
+      return Err(format!(
+        "Layer '{}' is {}x{} but the world is {}x{}.",
+        self.layers[layer].name,
+        img.width(),
+        img.height(),
+        self.width,
+        self.height
+      ));
+    }
+    self.layers[layer].tiles = decode_image(img);
+    self.resolve_nine_slices_at(layer, 0..self.width * self.height);
+    self.changes = WorldChanges::All;
+    Ok(())
+  }
+  /** Encodes a layer into the colour coded world.png format, see `decode_pixel`. */
+  pub fn to_image(&self, layer: usize) -> Image {
+    let mut img = Image::gen_image_color(self.width as u16, self.height as u16, BLANK);
+    img
+      .get_image_data_mut()
+      .iter_mut()
+      .zip(self.layers[layer].tiles.iter())
+      .for_each(|(pixel, tile)| *pixel = encode_pixel(tile.as_ref().map(|t| t.get_sprite_key())));
+    img
+  }
+  /** Builds a world from already decoded layers, e.g. from a map file. */
+  pub fn from_layers(width: usize, height: usize, layers: Vec<Layer>, camera: Camera) -> World {
+    assert!(!layers.is_empty(), "A world needs at least one layer.");
     World {
-      _scroll_pos: vec2(0.0, 0.0),
-      height: img.height(),
+      active_layer: 0,
+      camera,
+      changes: WorldChanges::All,
+      drawn_tiles: 0,
+      height,
+      layers,
       mouse_pos: (0.0, 0.0),
       mouse_grid: None,
       mouse_world_px: None,
-      prev_mouse_pos: (0.0, 0.0),
-      scroll_pos: vec2(0.0, 0.0),
-      tiles,
-      width: img.width(),
-      zoom: 2.0,
+      width,
     }
   }
+
+  /** Bottom to top. */
+  pub fn layers(&self) -> &[Layer] {
+    &self.layers
+  }
+  pub fn active_layer(&self) -> usize {
+    self.active_layer
+  }
+  pub fn set_active_layer(&mut self, layer: usize) {
+    self.active_layer = layer.min(self.layers.len() - 1);
+  }
+  pub fn toggle_layer_visible(&mut self, layer: usize) {
+    self.layers[layer].visible = !self.layers[layer].visible;
+    //The minimap shows the top visible tile.
+    self.changes = WorldChanges::All;
+  }
+  pub fn toggle_layer_locked(&mut self, layer: usize) {
+    self.layers[layer].locked = !self.layers[layer].locked;
+  }
+  /** Hidden layers are not edited either, the edit would not show. */
+  pub fn is_active_layer_editable(&self) -> bool {
+    let layer = &self.layers[self.active_layer];
+    layer.visible && !layer.locked
+  }
+
+  /** Tile of the active layer. */
   pub fn get_tile(&self, x: &usize, y: &usize) -> Option<&Tile> {
-    self.tiles[xy_to_i(&self.width, &x, &y)].as_ref()
-  }
-  // pub fn try_get_tile(&self, x: &isize, y: &isize) -> Option<&Tile> {
-  //   if x >= &0 && x < &(self.width as isize) && y >= &0 && y < &(self.height as isize) {
-  //     return self.get_tile(&(*x as usize), &(*y as usize));
-  //   }
-  //   None
-  // }
+    self.get_layer_tile(self.active_layer, x, y)
+  }
+  pub fn get_layer_tile(&self, layer: usize, x: &usize, y: &usize) -> Option<&Tile> {
+    self.layers[layer].tiles[xy_to_i(&self.width, x, y)].as_ref()
+  }
+  /** Topmost tile of the visible layers, what is seen at x,y. */
+  pub fn top_tile(&self, x: &usize, y: &usize) -> Option<&Tile> {
+    (0..self.layers.len())
+      .rev()
+      .filter(|l| self.layers[*l].visible)
+      .find_map(|l| self.get_layer_tile(l, x, y))
+  }
+  pub fn try_get_tile(&self, layer: usize, x: &isize, y: &isize) -> Option<&Tile> {
+    if x >= &0 && x < &(self.width as isize) && y >= &0 && y < &(self.height as isize) {
+      return self.get_layer_tile(layer, &(*x as usize), &(*y as usize));
+    }
+    None
+  }
+  /** Returns the 3x3 block of the active layer around x,y, see `get_layer_surrounding`. */
   pub fn get_surrounding(&self, x: &usize, y: &usize) -> Vec<Option<&Tile>> {
-    let mut surrounding = Vec::new();
-    for xi in x.max(&1) - 1..x.min(&(self.width - 2)) + 1 {
-      for yi in y.max(&1) - 1..y.min(&(self.height - 2)) + 1 {
-        surrounding.push(self.get_tile(&xi, &yi))
+    self.get_layer_surrounding(self.active_layer, x, y)
+  }
+  /** Returns the 3x3 block around x,y row by row, with `None` outside the world. */
+  pub fn get_layer_surrounding(&self, layer: usize, x: &usize, y: &usize) -> Vec<Option<&Tile>> {
+    let mut surrounding = Vec::with_capacity(9);
+    for yi in *y as isize - 1..=*y as isize + 1 {
+      for xi in *x as isize - 1..=*x as isize + 1 {
+        surrounding.push(self.try_get_tile(layer, &xi, &yi));
       }
     }
     surrounding
   }
-  pub fn get_tile_mut(&mut self, x: &usize, y: &usize) -> Option<&mut Tile> {
-    self.tiles[xy_to_i(&mut self.width, &x, &y)].as_mut()
+  /** Recomputes the blob mask of every nine-slice tile from its neighbours. */
+  pub fn resolve_nine_slices(&mut self) {
+    for layer in 0..self.layers.len() {
+      self.resolve_nine_slices_at(layer, 0..self.width * self.height);
+    }
+  }
+  /**
+  Recomputes the nine-slice tiles of a layer in the 3x3 block around every changed cell in one
+  pass. Returns the cells that changed along with their previous key.
+  */
+  pub fn resolve_nine_slices_around(
+    &mut self,
+    layer: usize,
+    changed: &[(usize, usize)],
+  ) -> Vec<((usize, usize), SpriteKey)> {
+    let mut affected = HashSet::new();
+    for (x, y) in changed {
+      for yi in y.saturating_sub(1)..=(y + 1).min(self.height - 1) {
+        for xi in x.saturating_sub(1)..=(x + 1).min(self.width - 1) {
+          affected.insert(xy_to_i(&self.width, &xi, &yi));
+        }
+      }
+    }
+    self.resolve_nine_slices_at(layer, affected.into_iter())
   }
+  fn resolve_nine_slices_at(
+    &mut self,
+    layer: usize,
+    indexes: impl Iterator<Item = usize>,
+  ) -> Vec<((usize, usize), SpriteKey)> {
+    //Read every mask before writing any, so the result does not depend on the order.
+    let masks: Vec<(usize, BlobMask)> = indexes
+      .filter_map(
+        |i| match self.layers[layer].tiles[i].as_ref()?.get_sprite_key() {
+          SpriteKey::Nine(nine) => {
+            let (x, y) = i_to_xy(&self.width, &i);
+            let mask =
+              BlobMask::from_surrounding(&self.get_layer_surrounding(layer, &x, &y), &nine.sg);
+            (mask != nine.blob).then_some((i, mask))
+          }
+          _ => None,
+        },
+      )
+      .collect();
 
-  pub fn draw(&mut self, deb: &DebugState, assets: &mut Assets) {
-    let zoom = self.zoom;
-    let scroll = (self.scroll_pos.x, self.scroll_pos.y);
-    for tile in self.tiles.iter_mut() {
-      if let Some(t) = tile {
-        t.draw(assets, scroll, zoom);
-        if deb.enabled && deb.tiles {
-          t.debug_draw(assets, zoom, &self.scroll_pos);
+    let mut prev_keys = Vec::with_capacity(masks.len());
+    for (i, mask) in masks {
+      if let Some(tile) = &mut self.layers[layer].tiles[i] {
+        let prev = tile.get_sprite_key().clone();
+        let mut key = prev.clone();
+        if let SpriteKey::Nine(nine) = &mut key {
+          nine.blob = mask;
         }
+        tile.set_visual(key);
+        prev_keys.push((i_to_xy(&self.width, &i), prev));
       }
     }
+    prev_keys
+  }
+  /** Cells whose sprite key was set since the last call. Blob mask updates are not included. */
+  pub fn take_changes(&mut self) -> WorldChanges {
+    std::mem::replace(&mut self.changes, WorldChanges::Cells(vec![]))
+  }
+  /** Replaces the visual of a cell, creating or removing its tile. Returns the previous key. */
+  pub fn set_sprite_key(
+    &mut self,
+    layer: usize,
+    x: &usize,
+    y: &usize,
+    sprite_key: Option<SpriteKey>,
+  ) -> Option<SpriteKey> {
+    if let WorldChanges::Cells(cells) = &mut self.changes {
+      cells.push((*x, *y));
+    }
+    let cell = &mut self.layers[layer].tiles[xy_to_i(&self.width, x, y)];
+    let prev = cell.as_ref().map(|t| t.get_sprite_key().clone());
+    match (cell.as_mut(), sprite_key) {
+      (Some(tile), Some(key)) => tile.set_visual(key),
+      (None, Some(key)) => *cell = Some(Tile::from_sprite_key(*x, *y, key)),
+      (_, None) => *cell = None,
+    }
+    prev
   }
 
-  pub fn update(&mut self) {
-    self.update_panning();
+  /** Size and sprites of every layer, see `restore`. */
+  pub fn snapshot(&self) -> WorldSnapshot {
+    WorldSnapshot {
+      width: self.width,
+      height: self.height,
+      layers: self
+        .layers
+        .iter()
+        .map(|layer| {
+          layer
+            .tiles
+            .iter()
+            .map(|t| t.as_ref().map(|t| t.get_sprite_key().clone()))
+            .collect()
+        })
+        .collect(),
+    }
+  }
+  /** Puts back the size and sprites of a snapshot. Layer names and flags are kept. */
+  pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+    self.width = snapshot.width;
+    self.height = snapshot.height;
+    for (layer, keys) in self.layers.iter_mut().zip(snapshot.layers.iter()) {
+      layer.tiles = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+          let (x, y) = i_to_xy(&snapshot.width, &i);
+          key.clone().map(|key| Tile::from_sprite_key(x, y, key))
+        })
+        .collect();
+    }
+    self.changes = WorldChanges::All;
+    self.camera.clamp(self.map_size());
     self.update_mouse_pos();
   }
 
+  /**
+  Grows or shrinks the map around `anchor`. New cells of the bottom layer get `fill`, the other
+  layers start out empty there. Sides are kept to 1 to `MAX_MAP_SIZE`. Cell coordinates change,
+  so go through `History::resize` to keep the history valid.
+  */
+  pub fn resize(&mut self, width: usize, height: usize, anchor: Anchor, fill: Option<SpriteKey>) {
+    let (width, height) = (width.clamp(1, MAX_MAP_SIZE), height.clamp(1, MAX_MAP_SIZE));
+    let (off_x, off_y) = anchor.offset((self.width, self.height), (width, height));
+    let old_width = self.width as isize;
+    let old_height = self.height as isize;
+    let from_old = |x: usize, y: usize| {
+      let (old_x, old_y) = (x as isize - off_x, y as isize - off_y);
+      (old_x >= 0 && old_x < old_width && old_y >= 0 && old_y < old_height)
+        .then_some((old_x as usize, old_y as usize))
+    };
+
+    let mut new_cells = vec![];
+    for (i, layer) in self.layers.iter_mut().enumerate() {
+      let mut tiles = Vec::with_capacity(width * height);
+      for y in 0..height {
+        for x in 0..width {
+          let tile = match from_old(x, y) {
+            Some((old_x, old_y)) => {
+              let mut tile = layer.tiles[xy_to_i(&self.width, &old_x, &old_y)].take();
+              if let Some(tile) = &mut tile {
+                tile.set_position(x, y);
+              }
+              tile
+            }
+            None => {
+              if i == 0 {
+                new_cells.push((x, y));
+              }
+              match (i, &fill) {
+                (0, Some(key)) => Some(Tile::from_sprite_key(x, y, key.clone())),
+                _ => None,
+              }
+            }
+          };
+          tiles.push(tile);
+        }
+      }
+      layer.tiles = tiles;
+    }
+    self.width = width;
+    self.height = height;
+
+    //Nine-slices along the new border lost or gained neighbours.
+    let border = (0..height)
+      .flat_map(|y| (0..width).map(move |x| (x, y)))
+      .filter(|(x, y)| *x == 0 || *y == 0 || *x == width - 1 || *y == height - 1);
+    new_cells.extend(border);
+    for layer in 0..self.layers.len() {
+      self.resolve_nine_slices_around(layer, &new_cells);
+    }
+
+    self.changes = WorldChanges::All;
+    self.camera.pos += vec2(off_x as f32, off_y as f32) * TILE_SIZE as f32;
+    self.camera.clamp(self.map_size());
+    self.update_mouse_pos();
+  }
+
+  /** Draws the visible layers bottom to top. Only the bottom layer fills in tile backgrounds. */
+  pub fn draw(&mut self, deb: &DebugState, assets: &mut Assets) {
+    let mut drawn = 0;
+    for (i, layer) in self.layers.iter().enumerate() {
+      if !layer.visible {
+        continue;
+      }
+      //A batch per layer, batches draw page by page and would mix up the layer order.
+      let mut batch = TileBatch::default();
+      for t in self.visible_tiles(i) {
+        t.draw(assets, &mut batch, &self.camera, i == 0);
+        drawn += 1;
+      }
+      batch.submit();
+    }
+
+    if deb.enabled && deb.tiles {
+      for t in self.visible_tiles(self.active_layer) {
+        t.debug_draw(assets, &self.camera);
+      }
+    }
+    self.drawn_tiles = drawn;
+  }
+  fn visible_tiles(&self, layer: usize) -> impl Iterator<Item = &Tile> {
+    let (xs, ys) = self.visible_grid();
+    ys.flat_map(move |y| xs.clone().map(move |x| (x, y)))
+      .filter_map(move |(x, y)| self.get_layer_tile(layer, &x, &y))
+  }
+
+  /** Columns and rows of the grid that are at least partly on screen. */
+  pub fn visible_grid(&self) -> (Range<usize>, Range<usize>) {
+    let top_left = self.camera.screen_to_world((0.0, 0.0));
+    let bottom_right = top_left + self.camera.view_size();
+    let range = |first: f32, last: f32, cells: usize| {
+      let first = (first / TILE_SIZE as f32).floor().max(0.0) as usize;
+      let last = (last / TILE_SIZE as f32).ceil().max(0.0) as usize;
+      first.min(cells)..last.min(cells)
+    };
+    (
+      range(top_left.x, bottom_right.x, self.width),
+      range(top_left.y, bottom_right.y, self.height),
+    )
+  }
+
+  /** See `Camera::update`. */
+  pub fn update(&mut self, mouse_over_ui: bool, keys_captured: bool) {
+    self
+      .camera
+      .update(self.map_size(), mouse_over_ui, keys_captured);
+    self.update_mouse_pos();
+  }
+
+  /** Size of the map in world pixels. */
+  pub fn map_size(&self) -> Vec2 {
+    vec2(
+      (self.width * TILE_SIZE) as f32,
+      (self.height * TILE_SIZE) as f32,
+    )
+  }
+  pub fn fit_to_screen(&mut self) {
+    self.camera.fit(self.map_size());
+  }
+  pub fn center_on_tile(&mut self, x: &usize, y: &usize) {
+    let half = TILE_SIZE as f32 / 2.0;
+    self.camera.center_on(vec2(
+      (x * TILE_SIZE) as f32 + half,
+      (y * TILE_SIZE) as f32 + half,
+    ));
+    self.camera.clamp(self.map_size());
+  }
+
   /** Returns world px for pos. pos defaults to mouse position. */
   pub fn pos_to_world_px(&self, pos: Option<&(f32, f32)>) -> Option<(usize, usize)> {
     let (x, y) = pos.unwrap_or(&self.mouse_pos);
-    let m_x = (x / self.zoom) - self.scroll_pos.x;
-    let m_y = (y / self.zoom) - self.scroll_pos.y;
+    let world = self.camera.screen_to_world((*x, *y));
+    let (m_x, m_y) = (world.x, world.y);
     let width = (self.width * TILE_SIZE) as f32;
     let height = (self.height * TILE_SIZE) as f32;
-    if m_x < 0.0 || m_x > width || m_y < 0.0 || m_y > height {
+    if m_x < 0.0 || m_x >= width || m_y < 0.0 || m_y >= height {
       return None;
     } else {
       return Some((m_x.floor() as usize, m_y.floor() as usize));
     }
   }
 
+  /** Grid cell under a screen position, also outside the world. */
+  pub fn screen_to_cell(&self, pos: &(f32, f32)) -> (isize, isize) {
+    let world = self.camera.screen_to_world(*pos) / TILE_SIZE as f32;
+    (world.x.floor() as isize, world.y.floor() as isize)
+  }
+  /** The cell as grid indexes when it is inside the world. */
+  pub fn cell_in_world(&self, cell: (isize, isize)) -> Option<(usize, usize)> {
+    let (x, y) = cell;
+    (x >= 0 && y >= 0 && x < self.width as isize && y < self.height as isize)
+      .then_some((x as usize, y as usize))
+  }
+
   /** Returns grid index for world_px. world_px defaults to mouse position. */
   pub fn px_to_grid(&self, world_px: Option<&(usize, usize)>) -> Option<(usize, usize)> {
     if let Some(px) = world_px {
@@ -178,41 +702,7 @@ impl World {
     None
   }
 
-  fn update_panning(&mut self) {
-    let mouse_diff = (
-      self.mouse_pos.0 - self.prev_mouse_pos.0,
-      self.mouse_pos.1 - self.prev_mouse_pos.1,
-    );
-    if is_mouse_button_down(MouseButton::Right) {
-      self.scroll_pos.x += mouse_diff.0 / self.zoom;
-      self.scroll_pos.y += mouse_diff.1 / self.zoom;
-      self._scroll_pos = self.scroll_pos;
-    } else {
-      let x_vel = match (
-        is_key_down(KeyCode::A) || is_key_down(KeyCode::Left),
-        is_key_down(KeyCode::D) || is_key_down(KeyCode::Right),
-      ) {
-        (true, false) => BASE_MOVEMENT_SPEED * get_frame_time(),
-        (false, true) => -BASE_MOVEMENT_SPEED * get_frame_time(),
-        _ => 0f32,
-      };
-      let y_vel = match (
-        is_key_down(KeyCode::W) || is_key_down(KeyCode::Up),
-        is_key_down(KeyCode::S) || is_key_down(KeyCode::Down),
-      ) {
-        (true, false) => BASE_MOVEMENT_SPEED * get_frame_time(),
-        (false, true) => -BASE_MOVEMENT_SPEED * get_frame_time(),
-        _ => 0f32,
-      };
-      if x_vel > 0.1 || x_vel < -0.1 || y_vel > 0.1 || y_vel < -0.1 {
-        self._scroll_pos = vec2(self._scroll_pos.x + x_vel, self._scroll_pos.y + y_vel);
-        self.scroll_pos = vec2(self._scroll_pos.x.round(), self._scroll_pos.y.round());
-      }
-    }
-  }
-
   fn update_mouse_pos(&mut self) {
-    self.prev_mouse_pos = self.mouse_pos;
     self.mouse_pos = mouse_position();
     self.mouse_world_px = self.pos_to_world_px(None);
 